@@ -0,0 +1,199 @@
+//! Source address access control for wake-on-LAN packets.
+//!
+//! Both the configuration and each hook may have lists of source networks to
+//! allow and deny. A packet from a denied network is always rejected; if there
+//! is an allow list, packets from networks not on it are rejected too.
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use error::Error;
+use event::Remote;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// A network in CIDR notation (e.g., `192.168.1.0/24` or `fd00::/8`).
+///
+/// A bare address is a network of just that address.
+pub struct Cidr {
+    /// The address of the network.
+    pub addr: IpAddr,
+
+    /// The number of leading bits of [`Cidr::addr`] that identify the network.
+    pub prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether or not the address is in this network.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = (!0u32)
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                (u32::from(network) ^ u32::from(addr)) & mask == 0
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = (!0u128)
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                (u128::from(network) ^ u128::from(addr)) & mask == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::CidrParseError(s.into());
+
+        let (addr, prefix_len) = match s.find('/') {
+            Some(index) => (&s[..index], Some(&s[index + 1..])),
+            None => (s, None),
+        };
+
+        let addr = addr.parse::<IpAddr>().map_err(|_| err())?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse::<u8>().map_err(|_| err())?,
+            None => max_prefix_len,
+        };
+
+        if prefix_len > max_prefix_len {
+            return Err(err());
+        }
+
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Why a packet was rejected.
+pub enum Rejection {
+    /// The source is in a denied network.
+    Denied(Cidr),
+
+    /// There is an allow list and the source is not in any of its networks.
+    NotAllowed,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rejection::Denied(ref cidr) => write!(f, "deny_sources: {}", cidr),
+            Rejection::NotAllowed => f.write_str("not in allow_sources"),
+        }
+    }
+}
+
+/// Check whether a packet from the given remote passes the allow and deny
+/// lists.
+///
+/// Raw Ethernet frames have no source IP address, so they pass any deny list
+/// but are rejected by any allow list.
+pub fn check(allow: &[Cidr], deny: &[Cidr], remote: &Remote) -> Result<(), Rejection> {
+    let source = match *remote {
+        Remote::Udp(addr) => Some(addr.ip()),
+        Remote::Ethernet(..) => None,
+    };
+
+    if let Some(source) = source {
+        if let Some(cidr) = deny.iter().find(|cidr| cidr.contains(source)) {
+            return Err(Rejection::Denied(*cidr));
+        }
+    }
+
+    if !allow.is_empty()
+        && !source
+            .map(|source| allow.iter().any(|cidr| cidr.contains(source)))
+            .unwrap_or(false)
+    {
+        return Err(Rejection::NotAllowed);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mac::MacAddress;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn udp(s: &str) -> Remote {
+        Remote::Udp(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            cidr("192.168.1.0/24"),
+            Cidr {
+                addr: "192.168.1.0".parse().unwrap(),
+                prefix_len: 24,
+            }
+        );
+        assert_eq!(
+            cidr("fd00::1"),
+            Cidr {
+                addr: "fd00::1".parse().unwrap(),
+                prefix_len: 128,
+            }
+        );
+        assert_eq!(cidr("10.0.0.0/8").to_string(), "10.0.0.0/8");
+
+        for s in &["", "/8", "10.0.0.0/", "10.0.0.0/33", "fd00::/129", "host/8"] {
+            assert_matches!(
+                s.parse::<Cidr>(),
+                Err(Error::CidrParseError(ref e)) if e == s
+            );
+        }
+    }
+
+    #[test]
+    fn test_contains() {
+        assert!(cidr("192.168.1.0/24").contains("192.168.1.42".parse().unwrap()));
+        assert!(!cidr("192.168.1.0/24").contains("192.168.2.42".parse().unwrap()));
+        assert!(cidr("192.168.1.5/24").contains("192.168.1.42".parse().unwrap()));
+        assert!(cidr("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+        assert!(cidr("10.0.0.1").contains("10.0.0.1".parse().unwrap()));
+        assert!(!cidr("10.0.0.1").contains("10.0.0.2".parse().unwrap()));
+        assert!(cidr("fd00::/8").contains("fd12:3456::1".parse().unwrap()));
+        assert!(!cidr("fd00::/8").contains("fe80::1".parse().unwrap()));
+        assert!(cidr("::/0").contains("::1".parse().unwrap()));
+        assert!(!cidr("0.0.0.0/0").contains("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_check() {
+        let allow = [cidr("192.168.1.0/24")];
+        let deny = [cidr("192.168.1.13")];
+
+        assert_eq!(check(&[], &[], &udp("10.0.0.1:9")), Ok(()));
+        assert_eq!(check(&allow, &deny, &udp("192.168.1.2:9")), Ok(()));
+        assert_eq!(
+            check(&allow, &deny, &udp("192.168.1.13:9")),
+            Err(Rejection::Denied(deny[0]))
+        );
+        assert_eq!(
+            check(&allow, &deny, &udp("10.0.0.1:9")),
+            Err(Rejection::NotAllowed)
+        );
+        assert_eq!(check(&[], &deny, &udp("10.0.0.1:9")), Ok(()));
+
+        let ethernet = Remote::Ethernet(MacAddress(1, 2, 3, 4, 5, 6));
+        assert_eq!(check(&[], &deny, &ethernet), Ok(()));
+        assert_eq!(check(&allow, &[], &ethernet), Err(Rejection::NotAllowed));
+    }
+}
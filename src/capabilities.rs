@@ -0,0 +1,355 @@
+//! Checking that the server has the capabilities its configuration needs.
+//!
+//! Rather than requiring root, the server only needs the Linux capabilities
+//! for what it is configured to do, which may be granted to an unprivileged
+//! user (e.g., with systemd's `AmbientCapabilities=`).
+
+use std::{fmt, fs, io};
+
+use config::Config;
+
+/// The first port that unprivileged processes may bind if
+/// `net.ipv4.ip_unprivileged_port_start` cannot be read.
+const DEFAULT_UNPRIVILEGED_PORT_START: u16 = 1024;
+
+/// The first kernel version in which binding a socket to an interface with
+/// `SO_BINDTODEVICE` does not require `CAP_NET_RAW`.
+const UNPRIVILEGED_BIND_TO_DEVICE_VERSION: (u32, u32) = (5, 7);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// A Linux capability that the server may need.
+pub enum Capability {
+    /// `CAP_SETGID`, to change groups.
+    SetGid,
+
+    /// `CAP_SETUID`, to change users.
+    SetUid,
+
+    /// `CAP_NET_BIND_SERVICE`, to bind privileged ports.
+    NetBindService,
+
+    /// `CAP_NET_RAW`, to open raw packet sockets.
+    NetRaw,
+}
+
+impl Capability {
+    /// The bit of the capability in a capability set.
+    fn bit(self) -> u32 {
+        match self {
+            Capability::SetGid => 6,
+            Capability::SetUid => 7,
+            Capability::NetBindService => 10,
+            Capability::NetRaw => 13,
+        }
+    }
+
+    /// The name of the capability.
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::SetGid => "CAP_SETGID",
+            Capability::SetUid => "CAP_SETUID",
+            Capability::NetBindService => "CAP_NET_BIND_SERVICE",
+            Capability::NetRaw => "CAP_NET_RAW",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// A capability the configuration requires that the server does not have.
+pub struct MissingCapability {
+    /// The missing capability.
+    pub capability: Capability,
+
+    /// What the capability is needed for.
+    pub reason: String,
+}
+
+impl fmt::Display for MissingCapability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} requires {}", self.reason, self.capability)
+    }
+}
+
+/// Find the capabilities that the configuration requires but that the server
+/// does not have.
+///
+/// If the server was socket activated, it does not bind any UDP ports itself.
+pub fn missing(config: &Config, socket_activated: bool) -> io::Result<Vec<MissingCapability>> {
+    let effective = effective_capabilities()?;
+
+    Ok(required(
+        config,
+        socket_activated,
+        unprivileged_port_start(),
+        kernel_version(),
+    )
+    .into_iter()
+    .filter(|required| effective & (1 << required.capability.bit()) == 0)
+    .collect())
+}
+
+/// Determine the capabilities that the configuration requires.
+///
+/// If the kernel version is unknown, it is assumed to be too old to bind
+/// sockets to interfaces without `CAP_NET_RAW`.
+fn required(
+    config: &Config,
+    socket_activated: bool,
+    unprivileged_port_start: u16,
+    kernel_version: Option<(u32, u32)>,
+) -> Vec<MissingCapability> {
+    let mut required = Vec::new();
+
+    // Port 0 binds an ephemeral port, which does not need any privileges.
+    let privileged_ports = config
+        .ports
+        .iter()
+        .filter(|&&port| port != 0 && port < unprivileged_port_start)
+        .map(|port| port.to_string())
+        .collect::<Vec<_>>();
+
+    if !socket_activated && !privileged_ports.is_empty() {
+        required.push(MissingCapability {
+            capability: Capability::NetBindService,
+            reason: format!(
+                "Listening on privileged UDP ports ({})",
+                privileged_ports.join(", ")
+            ),
+        });
+    }
+
+    let bind_to_device_privileged = kernel_version
+        .map(|version| version < UNPRIVILEGED_BIND_TO_DEVICE_VERSION)
+        .unwrap_or(true);
+
+    if !socket_activated && !config.interfaces.is_empty() && bind_to_device_privileged {
+        required.push(MissingCapability {
+            capability: Capability::NetRaw,
+            reason: format!(
+                "Binding UDP listeners to network interfaces ({}) on Linux before {}.{}",
+                config.interfaces.join(", "),
+                UNPRIVILEGED_BIND_TO_DEVICE_VERSION.0,
+                UNPRIVILEGED_BIND_TO_DEVICE_VERSION.1
+            ),
+        });
+    }
+
+    if config.ethernet {
+        required.push(MissingCapability {
+            capability: Capability::NetRaw,
+            reason: "Listening for raw Ethernet frames (disable with --no-ethernet)".into(),
+        });
+    }
+
+    if let Some(ref user) = config.user {
+        required.push(MissingCapability {
+            capability: Capability::SetUid,
+            reason: format!("Switching to user `{}`", user),
+        });
+    }
+
+    // Clearing supplementary groups requires CAP_SETGID even if the group does
+    // not change.
+    if config.user.is_some() || config.group.is_some() {
+        required.push(MissingCapability {
+            capability: Capability::SetGid,
+            reason: "Switching groups".into(),
+        });
+    }
+
+    required
+}
+
+/// Read the effective capability set of the server.
+fn effective_capabilities() -> io::Result<u64> {
+    let status = fs::read_to_string("/proc/self/status")?;
+
+    parse_effective_capabilities(&status).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "could not find CapEff in /proc/self/status",
+        )
+    })
+}
+
+/// Parse the effective capability set from the contents of
+/// `/proc/self/status`.
+fn parse_effective_capabilities(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|line| line.starts_with("CapEff:"))
+        .and_then(|line| u64::from_str_radix(line["CapEff:".len()..].trim(), 16).ok())
+}
+
+/// Read the version of the running kernel.
+fn kernel_version() -> Option<(u32, u32)> {
+    fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .and_then(|release| parse_kernel_version(&release))
+}
+
+/// Parse the major and minor version from a kernel release (e.g.,
+/// `5.4.0-42-generic`).
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.trim().split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+
+    Some((major, minor))
+}
+
+/// Read the first port that unprivileged processes may bind.
+fn unprivileged_port_start() -> u16 {
+    fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(DEFAULT_UNPRIVILEGED_PORT_START)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+
+    fn capabilities(required: Vec<MissingCapability>) -> Vec<Capability> {
+        required
+            .into_iter()
+            .map(|required| required.capability)
+            .collect()
+    }
+
+    #[test]
+    fn test_required() {
+        let mut config = Config::from_str(
+            r#"
+            [[hook]]
+            name = "vm1"
+            mac_address = "aa:bb:cc:dd:ee:01"
+            command = ["true"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            required(&config, false, 1024, Some((5, 7))),
+            vec![
+                MissingCapability {
+                    capability: Capability::NetBindService,
+                    reason: "Listening on privileged UDP ports (7, 9)".into(),
+                },
+                MissingCapability {
+                    capability: Capability::NetRaw,
+                    reason: "Listening for raw Ethernet frames (disable with --no-ethernet)".into(),
+                },
+            ]
+        );
+        assert_eq!(
+            capabilities(required(&config, true, 1024, Some((5, 7)))),
+            vec![Capability::NetRaw]
+        );
+
+        config.ethernet = false;
+        assert_eq!(
+            capabilities(required(&config, false, 8, Some((5, 7)))),
+            vec![Capability::NetBindService]
+        );
+        assert_eq!(
+            capabilities(required(&config, false, 0, Some((5, 7)))),
+            vec![]
+        );
+
+        config.ports = vec![0, 4009];
+        assert_eq!(
+            capabilities(required(&config, false, 1024, Some((5, 7)))),
+            vec![]
+        );
+
+        config.group = Some("nogroup".into());
+        assert_eq!(
+            capabilities(required(&config, false, 1024, Some((5, 7)))),
+            vec![Capability::SetGid]
+        );
+
+        config.user = Some("nobody".into());
+        assert_eq!(
+            capabilities(required(&config, false, 1024, Some((5, 7)))),
+            vec![Capability::SetUid, Capability::SetGid]
+        );
+    }
+
+    #[test]
+    fn test_required_interfaces() {
+        let mut config = Config::from_str(
+            r#"
+            ports = [4009]
+            interfaces = ["br-vm"]
+            ethernet = false
+
+            [[hook]]
+            name = "vm1"
+            mac_address = "aa:bb:cc:dd:ee:01"
+            command = ["true"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            required(&config, false, 1024, Some((5, 4))),
+            vec![MissingCapability {
+                capability: Capability::NetRaw,
+                reason: "Binding UDP listeners to network interfaces (br-vm) on Linux before 5.7"
+                    .into(),
+            }]
+        );
+        assert_eq!(
+            capabilities(required(&config, false, 1024, None)),
+            vec![Capability::NetRaw]
+        );
+        assert_eq!(
+            capabilities(required(&config, false, 1024, Some((5, 7)))),
+            vec![]
+        );
+        assert_eq!(
+            capabilities(required(&config, false, 1024, Some((6, 1)))),
+            vec![]
+        );
+
+        // Sockets passed by systemd are already bound.
+        assert_eq!(
+            capabilities(required(&config, true, 1024, Some((5, 4)))),
+            vec![]
+        );
+
+        config.interfaces = vec![];
+        assert_eq!(
+            capabilities(required(&config, false, 1024, Some((5, 4)))),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_parse_kernel_version() {
+        assert_eq!(parse_kernel_version("5.4.0-42-generic\n"), Some((5, 4)));
+        assert_eq!(parse_kernel_version("6.18.44-fc-v139"), Some((6, 18)));
+        assert_eq!(parse_kernel_version("4.19"), Some((4, 19)));
+        assert_eq!(parse_kernel_version("garbage"), None);
+    }
+
+    #[test]
+    fn test_parse_effective_capabilities() {
+        assert_eq!(
+            parse_effective_capabilities(
+                "Name:\twake-on-lan-hoo\nCapInh:\t0000000000000000\nCapPrm:\t0000000000002400\nCapEff:\t0000000000002400\n"
+            ),
+            Some(1 << 10 | 1 << 13)
+        );
+        assert_eq!(parse_effective_capabilities("Name:\tsh\n"), None);
+    }
+}
@@ -0,0 +1,32 @@
+//! Measuring time between events.
+
+use std::time::{Duration, Instant};
+
+/// The time that has passed between `earlier` and `now`, or zero if `now` is
+/// not later.
+///
+/// Instants are taken before the state they are compared against is locked, so
+/// `now` may be slightly earlier than an instant recorded by another thread.
+/// `Instant::duration_since` would panic in that case.
+pub fn elapsed(earlier: Instant, now: Instant) -> Duration {
+    if now > earlier {
+        now - earlier
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_elapsed() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(3);
+
+        assert_eq!(elapsed(now, later), Duration::from_secs(3));
+        assert_eq!(elapsed(now, now), Duration::from_secs(0));
+        assert_eq!(elapsed(later, now), Duration::from_secs(0));
+    }
+}
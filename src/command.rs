@@ -0,0 +1,364 @@
+//! Running hook commands.
+
+use std::{
+    io,
+    os::unix::process::CommandExt as UnixCommandExt,
+    process::{Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use futures::sync::oneshot;
+use libc;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use slog;
+use tokio::{
+    codec::{Decoder, FramedRead},
+    prelude::*,
+    timer::Delay,
+};
+use tokio_process::CommandExt;
+
+use config::Hook;
+use event::WakeEvent;
+
+/// How long a command has to exit after it has been sent `SIGTERM` before it
+/// is sent `SIGKILL`.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// The longest line of command output that will be logged as a single line.
+///
+/// Longer lines are split.
+const MAX_LINE_LEN: usize = 4096;
+
+/// How much of each of a command's output streams will be logged.
+///
+/// Further output is read but discarded.
+const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Why a command was terminated before it exited on its own.
+enum Interruption {
+    /// The command did not exit before the hook's timeout.
+    TimedOut,
+
+    /// The command was cancelled.
+    Cancelled,
+}
+
+/// How a command finished.
+enum Outcome {
+    /// The command exited on its own.
+    Exited(ExitStatus),
+
+    /// The command was interrupted and its process group was sent the given
+    /// signal.
+    ///
+    /// `SIGTERM` is sent first and `SIGKILL` is only sent if the command does
+    /// not exit within [`KILL_GRACE_PERIOD`].
+    Terminated(Interruption, Signal),
+}
+
+/// Run the command for the given hook in response to a wake event.
+///
+/// The details of the event are passed to the command as environment variables
+/// (see [`WakeEvent::environment()`]) and substituted into its arguments (see
+/// [`Template::expand()`][::template::Template::expand]).
+///
+/// The command is run in its own process group so that, if the hook has a
+/// timeout or `cancel` is signalled, the command and everything it started can
+/// be terminated together.
+///
+/// Each line the command writes to stdout or stderr is logged as it is written,
+/// up to [`MAX_OUTPUT_LEN`] bytes per stream.
+///
+/// The returned future logs the outcome of the command and always succeeds so
+/// that a failing command does not stop the server from handling packets.
+pub fn run(
+    log: slog::Logger,
+    hook: &Hook,
+    event: &WakeEvent,
+    cancel: oneshot::Receiver<()>,
+) -> impl Future<Item = (), Error = ()> {
+    assert!(!hook.command.is_empty());
+    let cmd = hook
+        .command
+        .iter()
+        .map(|arg| arg.expand(&hook.name, event))
+        .collect::<Vec<_>>();
+
+    let log = log.new(o!{"command" => format!("{:?}", cmd)});
+
+    let mut command = Command::new(&cmd[0]);
+    command
+        .args(&cmd[1..])
+        .envs(event.environment(&hook.name))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+
+    let mut child = match command.spawn_async() {
+        Ok(child) => child,
+        Err(e) => {
+            crit!(log, "failed to communicate with process"; "error" => %e);
+            return future::Either::A(future::ok(()));
+        }
+    };
+
+    let process_group = Pid::from_raw(-(child.id() as i32));
+    let timeout = hook.timeout;
+
+    let stdout = log_output(log.clone(), "stdout", child.stdout().take());
+    let stderr = log_output(log.clone(), "stderr", child.stderr().take());
+    let wait = child.join3(stdout, stderr).map(|(status, _, _)| status);
+
+    future::Either::B(
+        wait_with_timeout(wait, process_group, timeout, cancel).then(move |result| {
+            match result {
+                Err(e) => {
+                    crit!(log, "failed to communicate with process"; "error" => %e);
+                }
+
+                Ok(Outcome::Exited(status)) => {
+                    if status.success() {
+                        info!(log, "Command executed successfully");
+                    } else {
+                        error!(log, "Command executed unsuccessfully"; "status" => status.code());
+                    }
+                }
+
+                Ok(Outcome::Terminated(Interruption::TimedOut, signal)) => {
+                    error!(
+                        log,
+                        "Command timed out";
+                        "timeout" => ?timeout.expect("timed out without a timeout"),
+                        "signal" => ?signal,
+                    );
+                }
+
+                Ok(Outcome::Terminated(Interruption::Cancelled, signal)) => {
+                    warn!(log, "Command cancelled"; "signal" => ?signal);
+                }
+            }
+
+            Ok(())
+        }),
+    )
+}
+
+/// Log each line of one of a command's output streams as it is written.
+///
+/// Only the first [`MAX_OUTPUT_LEN`] bytes are logged.
+fn log_output<R>(
+    log: slog::Logger,
+    stream: &'static str,
+    output: Option<R>,
+) -> impl Future<Item = (), Error = io::Error>
+where
+    R: AsyncRead,
+{
+    let output = match output {
+        Some(output) => output,
+        None => return future::Either::A(future::ok(())),
+    };
+
+    future::Either::B(
+        FramedRead::new(output, OutputLines)
+            .fold(0, move |logged, line| {
+                if logged < MAX_OUTPUT_LEN {
+                    info!(log, "{}", utf8_or_raw(&line); "stream" => stream);
+
+                    if logged + line.len() >= MAX_OUTPUT_LEN {
+                        warn!(
+                            log,
+                            "Command output limit reached; discarding further output";
+                            "stream" => stream,
+                            "limit" => MAX_OUTPUT_LEN,
+                        );
+                    }
+                }
+
+                Ok::<_, io::Error>(logged + line.len())
+            })
+            .map(|_| ()),
+    )
+}
+
+/// A decoder that splits command output into lines.
+///
+/// Lines longer than [`MAX_LINE_LEN`] are split and trailing newlines are
+/// removed.
+struct OutputLines;
+
+impl Decoder for OutputLines {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let line = match buf.iter().take(MAX_LINE_LEN).position(|&b| b == b'\n') {
+            Some(newline) => {
+                let mut line = buf.split_to(newline + 1);
+                line.truncate(newline);
+                if line.ends_with(b"\r") {
+                    line.truncate(newline - 1);
+                }
+                line
+            }
+            None if buf.len() >= MAX_LINE_LEN => buf.split_to(MAX_LINE_LEN),
+            None => return Ok(None),
+        };
+
+        Ok(Some(line.to_vec()))
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(buf)? {
+            Some(line) => Ok(Some(line)),
+            None if buf.is_empty() => Ok(None),
+            None => Ok(Some(buf.take().to_vec())),
+        }
+    }
+}
+
+/// Wait for a command to finish.
+///
+/// If the command has not finished before the timeout or `cancel` is signalled,
+/// its process group will be terminated (see [`terminate()`]).
+fn wait_with_timeout<W>(
+    wait: W,
+    process_group: Pid,
+    timeout: Option<Duration>,
+    cancel: oneshot::Receiver<()>,
+) -> impl Future<Item = Outcome, Error = io::Error>
+where
+    W: Future<Item = ExitStatus, Error = io::Error>,
+{
+    let timed_out: Box<dyn Future<Item = Interruption, Error = ()> + Send> = match timeout {
+        Some(timeout) => Box::new(
+            Delay::new(Instant::now() + timeout)
+                .map(|_| Interruption::TimedOut)
+                .map_err(|_| ()),
+        ),
+        None => Box::new(future::empty()),
+    };
+
+    // If the sender is dropped, the command can no longer be cancelled.
+    let cancelled = cancel
+        .map(|_| Interruption::Cancelled)
+        .or_else(|_| future::empty());
+
+    let interrupted = timed_out
+        .select(cancelled)
+        .map(|(interruption, _)| interruption)
+        .map_err(|_| ());
+
+    wait.select2(interrupted).then(move |result| match result {
+        Ok(future::Either::A((status, _))) => {
+            future::Either::A(future::ok(Outcome::Exited(status)))
+        }
+        Err(future::Either::A((e, _))) => future::Either::A(future::err(e)),
+        Ok(future::Either::B((interruption, wait))) => future::Either::B(future::Either::A(
+            terminate(wait, process_group, interruption),
+        )),
+        Err(future::Either::B((_, wait))) => {
+            future::Either::B(future::Either::B(wait.map(Outcome::Exited)))
+        }
+    })
+}
+
+/// Terminate a command's process group.
+///
+/// The process group is sent `SIGTERM` and, if the command has not exited
+/// within [`KILL_GRACE_PERIOD`], `SIGKILL`.
+fn terminate<W>(
+    wait: W,
+    process_group: Pid,
+    interruption: Interruption,
+) -> impl Future<Item = Outcome, Error = io::Error>
+where
+    W: Future<Item = ExitStatus, Error = io::Error>,
+{
+    let _ = kill(process_group, Signal::SIGTERM);
+
+    wait.select2(Delay::new(Instant::now() + KILL_GRACE_PERIOD))
+        .then(move |result| match result {
+            Ok(future::Either::A(..)) => future::Either::A(future::ok(Outcome::Terminated(
+                interruption,
+                Signal::SIGTERM,
+            ))),
+            Err(future::Either::A((e, _))) => future::Either::A(future::err(e)),
+            Ok(future::Either::B((_, wait))) | Err(future::Either::B((_, wait))) => {
+                let _ = kill(process_group, Signal::SIGKILL);
+                future::Either::B(
+                    wait.map(move |_| Outcome::Terminated(interruption, Signal::SIGKILL)),
+                )
+            }
+        })
+}
+
+/// Attempt to parse the bytes as UTF-8.
+///
+/// If the bytes cannot be parsed as UTF-8 successfully, the `Debug`
+/// representation of the bytes will be used instead.
+fn utf8_or_raw(bytes: &[u8]) -> String {
+    ::std::str::from_utf8(bytes)
+        .map(Into::into)
+        .unwrap_or_else(|_| format!("{:?}", bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Vec<Vec<u8>> {
+        let mut buf = BytesMut::from(input);
+        let mut lines = Vec::new();
+
+        while let Some(line) = OutputLines.decode(&mut buf).unwrap() {
+            lines.push(line);
+        }
+        while let Some(line) = OutputLines.decode_eof(&mut buf).unwrap() {
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    #[test]
+    fn test_output_lines() {
+        assert_eq!(decode(b""), Vec::<Vec<u8>>::new());
+        assert_eq!(
+            decode(b"one\ntwo\r\n\nthree"),
+            vec![
+                b"one".to_vec(),
+                b"two".to_vec(),
+                b"".to_vec(),
+                b"three".to_vec(),
+            ]
+        );
+
+        let long_line = vec![b'x'; MAX_LINE_LEN + 10];
+        let mut input = long_line.clone();
+        input.push(b'\n');
+        assert_eq!(
+            decode(&input),
+            vec![
+                long_line[..MAX_LINE_LEN].to_vec(),
+                long_line[MAX_LINE_LEN..].to_vec(),
+            ]
+        );
+    }
+}
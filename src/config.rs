@@ -0,0 +1,955 @@
+//! Configuration for `wake-on-lan-hook`.
+//!
+//! A configuration file is a TOML document that declares one or more hooks,
+//! each of which runs a command when a wake-on-LAN packet for its MAC address
+//! is received:
+//!
+//! ```toml
+//! addresses = ["192.168.1.2", "::"]
+//! ports = [7, 9, 4343]
+//! bind_policy = [9]
+//! interfaces = ["br-vm", "br-lab"]
+//! ethernet = true
+//! scan_payloads = true
+//! max_concurrent_commands = 4
+//! shutdown_grace_period = 30
+//! deduplication_window = 500
+//! allow_sources = ["192.168.1.0/24", "fd00::/8"]
+//! deny_sources = ["192.168.1.13"]
+//! rate_limit = { rate = 100, burst = 200 }
+//! source_rate_limit = { rate = 5 }
+//! rate_limit_summary_interval = 60
+//! user = "wake-on-lan"
+//! group = "wake-on-lan"
+//!
+//! [[hook]]
+//! name = "vm1"
+//! mac_address = "f0:0f:f0:0f:f0:01"
+//! command = ["systemctl", "start", "vm1.service"]
+//!
+//! [[hook]]
+//! name = "vm2"
+//! mac_address = "f0:0f:f0:0f:f0:02"
+//! password = "192.168.1.1"
+//! command = ["systemctl", "start", "{hook}.service"]
+//! timeout = 30
+//! cooldown = 10
+//! concurrency = "queue"
+//! interfaces = ["br-vm"]
+//! destination_types = ["broadcast", "subnet-broadcast"]
+//! allow_sources = ["192.168.1.0/28"]
+//! ```
+//!
+//! See the [`template`][::template] module for the placeholders that may be used
+//! in commands.
+
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::{de, Deserialize, Deserializer};
+use toml;
+
+use acl::Cidr;
+use error::Error;
+use event::DestinationType;
+use mac::{MacAddress, SecureOnPassword};
+use ratelimit::RateLimit;
+use template::Template;
+
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// The configuration of the wake-on-lan-hook server.
+pub struct Config {
+    #[serde(default = "default_addresses")]
+    /// The IP addresses to listen for UDP wake-on-LAN packets on.
+    ///
+    /// If the IPv6 unspecified address (`::`) is included, the IPv6 all-nodes
+    /// multicast group is joined as well.
+    pub addresses: Vec<IpAddr>,
+
+    #[serde(default = "default_ports")]
+    /// The UDP ports to listen for wake-on-LAN packets on.
+    pub ports: Vec<u16>,
+
+    #[serde(default = "default_bind_policy")]
+    /// What to do when some of the UDP addresses and ports cannot be bound.
+    pub bind_policy: BindPolicy,
+
+    #[serde(default)]
+    /// The network interfaces to listen on.
+    ///
+    /// If set, every listener is bound to each of these interfaces (with
+    /// `SO_BINDTODEVICE`) and packets that arrive on other interfaces are not
+    /// received. Before Linux 5.7, binding to an interface requires
+    /// `CAP_NET_RAW`. If unset, packets are received on every interface.
+    pub interfaces: Vec<String>,
+
+    #[serde(default = "default_ethernet")]
+    /// Whether or not to listen for raw Ethernet wake-on-LAN frames.
+    pub ethernet: bool,
+
+    #[serde(default)]
+    /// Whether or not to search payloads for a magic packet.
+    ///
+    /// If set, the magic packet's sync stream may appear anywhere in a
+    /// payload (e.g., after a header added by the sending tool, or followed by
+    /// padding), rather than making up the whole payload.
+    pub scan_payloads: bool,
+
+    #[serde(default)]
+    /// The maximum number of commands that may run at once, across all hooks.
+    ///
+    /// Commands that are triggered while this many commands are running wait
+    /// until another command finishes. If unset, there is no limit.
+    pub max_concurrent_commands: Option<usize>,
+
+    #[serde(
+        default = "default_shutdown_grace_period",
+        deserialize_with = "seconds"
+    )]
+    /// How long running commands have to finish when the server shuts down
+    /// before they are terminated.
+    ///
+    /// This is given in seconds in the configuration file.
+    pub shutdown_grace_period: Duration,
+
+    #[serde(
+        default = "default_deduplication_window",
+        deserialize_with = "milliseconds"
+    )]
+    /// How long after a wake-on-LAN packet arrives identical copies of it (for
+    /// the same MAC address, from the same sender) are dropped.
+    ///
+    /// This is given in milliseconds in the configuration file. Zero disables
+    /// deduplication.
+    pub deduplication_window: Duration,
+
+    #[serde(default, deserialize_with = "vec_from_str")]
+    /// The networks that wake-on-LAN packets must be sent from.
+    ///
+    /// Each network is an IP address with an optional prefix length (e.g.,
+    /// `192.168.1.0/24`). Raw Ethernet frames have no source IP address, so
+    /// they are rejected if this is set. If unset, packets from any source are
+    /// accepted.
+    pub allow_sources: Vec<Cidr>,
+
+    #[serde(default, deserialize_with = "vec_from_str")]
+    /// The networks that wake-on-LAN packets must not be sent from.
+    ///
+    /// This takes precedence over [`Config::allow_sources`].
+    pub deny_sources: Vec<Cidr>,
+
+    #[serde(default)]
+    /// The rate limit for all incoming packets.
+    ///
+    /// Packets over the limit are dropped before anything else is done with
+    /// them. If unset, there is no limit.
+    pub rate_limit: Option<RateLimit>,
+
+    #[serde(default)]
+    /// The rate limit for incoming packets from each source IP address (or,
+    /// for raw Ethernet frames, each source hardware address).
+    ///
+    /// If unset, there is no limit.
+    pub source_rate_limit: Option<RateLimit>,
+
+    #[serde(
+        default = "default_rate_limit_summary_interval",
+        deserialize_with = "seconds"
+    )]
+    /// How often to log a summary of the packets dropped by the rate limits.
+    ///
+    /// This is given in seconds in the configuration file. Nothing is logged
+    /// for intervals in which no packets were dropped.
+    pub rate_limit_summary_interval: Duration,
+
+    #[serde(default)]
+    /// The user to switch to once the listeners have been bound.
+    ///
+    /// This may be a name or a numeric ID. Commands are run as this user. If
+    /// unset, the server keeps running as the user it was started as.
+    pub user: Option<String>,
+
+    #[serde(default)]
+    /// The group to switch to once the listeners have been bound.
+    ///
+    /// This may be a name or a numeric ID. If unset but [`Config::user`] is
+    /// set, the user's primary group is used. Supplementary groups are cleared
+    /// either way.
+    pub group: Option<String>,
+
+    #[serde(rename = "hook")]
+    /// The hooks to run when wake-on-LAN packets are received.
+    pub hooks: Vec<Hook>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// A command to run when a wake-on-LAN packet for a MAC address is received.
+pub struct Hook {
+    /// The name of the hook, which is used to identify it in logs.
+    pub name: String,
+
+    #[serde(deserialize_with = "from_str")]
+    /// The MAC address to listen for wake-on-LAN packets for.
+    ///
+    /// This may be written in any [`Notation`][::mac::Notation] (e.g.,
+    /// `aa:bb:cc:dd:ee:ff` or `aabb.ccdd.eeff`).
+    pub mac_address: MacAddress,
+
+    #[serde(default, deserialize_with = "option_from_str")]
+    /// A SecureOn password that wake-on-LAN packets must include.
+    pub password: Option<SecureOnPassword>,
+
+    #[serde(deserialize_with = "vec_from_str")]
+    /// The command to execute when a wake-on-LAN packet is received.
+    ///
+    /// Each argument is a [`Template`].
+    pub command: Vec<Template>,
+
+    #[serde(default, deserialize_with = "option_seconds")]
+    /// How long the command may run before it is terminated.
+    ///
+    /// This is given in seconds in the configuration file. If unset, the
+    /// command may run forever.
+    pub timeout: Option<Duration>,
+
+    #[serde(default, deserialize_with = "option_seconds")]
+    /// How long to ignore further wake-on-LAN packets after the hook has been
+    /// triggered.
+    ///
+    /// This is given in seconds in the configuration file. With the
+    /// `parallel` concurrency policy, packets are also ignored while the
+    /// command is still running; with the other policies, packets after the
+    /// cooldown are handled by the policy. The cooldown applies to this
+    /// hook and the packet's target MAC address only; other hooks for the same
+    /// MAC address have their own. If unset, every packet triggers the hook.
+    pub cooldown: Option<Duration>,
+
+    #[serde(default = "default_concurrency")]
+    /// What to do when the hook is triggered while its command is running.
+    pub concurrency: ConcurrencyPolicy,
+
+    #[serde(default)]
+    /// The network interfaces that packets must arrive on to trigger the hook.
+    ///
+    /// If [`Config::interfaces`] is set, each interface must also be listed
+    /// there. If unset, packets arriving on any interface trigger the hook.
+    pub interfaces: Vec<String>,
+
+    #[serde(default)]
+    /// The kinds of destination address that packets must be sent to to
+    /// trigger the hook.
+    ///
+    /// If unset, packets sent to any address trigger the hook.
+    pub destination_types: Vec<DestinationType>,
+
+    #[serde(default, deserialize_with = "vec_from_str")]
+    /// The networks that packets must be sent from to trigger the hook.
+    ///
+    /// This applies in addition to [`Config::allow_sources`].
+    pub allow_sources: Vec<Cidr>,
+
+    #[serde(default, deserialize_with = "vec_from_str")]
+    /// The networks that packets must not be sent from to trigger the hook.
+    ///
+    /// This applies in addition to [`Config::deny_sources`].
+    pub deny_sources: Vec<Cidr>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// What to do when a hook is triggered while its command is already running.
+pub enum ConcurrencyPolicy {
+    /// Run another copy of the command alongside the running one.
+    Parallel,
+
+    /// Ignore the trigger.
+    Single,
+
+    /// Run the command again once the running one has finished.
+    Queue,
+
+    /// Cancel the running command and then run the command again.
+    Replace,
+}
+
+impl FromStr for ConcurrencyPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parallel" => Ok(ConcurrencyPolicy::Parallel),
+            "single" => Ok(ConcurrencyPolicy::Single),
+            "queue" => Ok(ConcurrencyPolicy::Queue),
+            "replace" => Ok(ConcurrencyPolicy::Replace),
+            _ => Err(Error::ConcurrencyPolicyParseError(s.into())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// What to do when some of the UDP addresses and ports cannot be bound.
+///
+/// In the configuration file, this is either `"all"`, `"any"`, or a list of
+/// ports.
+pub enum BindPolicy {
+    /// Every address and port must be bound.
+    All,
+
+    /// At least one address and port must be bound.
+    Any,
+
+    /// The given ports must be bound on every address; failures to bind other
+    /// ports are tolerated.
+    Ports(Vec<u16>),
+}
+
+impl BindPolicy {
+    /// Whether a failure to bind the given port can be tolerated.
+    pub fn tolerates(&self, port: u16) -> bool {
+        match *self {
+            BindPolicy::All => false,
+            BindPolicy::Any => true,
+            BindPolicy::Ports(ref ports) => !ports.contains(&port),
+        }
+    }
+}
+
+impl FromStr for BindPolicy {
+    type Err = Error;
+
+    /// Parse a bind policy from `all`, `any`, or a comma-separated list of
+    /// ports.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(BindPolicy::All),
+            "any" => Ok(BindPolicy::Any),
+            _ => s
+                .split(',')
+                .map(|port| port.trim().parse())
+                .collect::<Result<Vec<_>, _>>()
+                .map(BindPolicy::Ports)
+                .map_err(|_| Error::BindPolicyParseError(s.into())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BindPolicy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Name(String),
+            Ports(Vec<u16>),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Name(name) => match name.as_str() {
+                "all" | "any" => name.parse().map_err(de::Error::custom),
+                _ => Err(de::Error::custom(Error::BindPolicyParseError(name))),
+            },
+            Raw::Ports(ports) => Ok(BindPolicy::Ports(ports)),
+        }
+    }
+}
+
+impl Config {
+    /// Read and validate the configuration file at the given path.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::ConfigReadError(path.display().to_string(), e))?;
+
+        contents.parse()
+    }
+
+    /// Ensure that the configuration is usable.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.addresses.is_empty() {
+            return Err(Error::ConfigError(
+                "at least one address is required".into(),
+            ));
+        }
+
+        if self.ports.is_empty() {
+            return Err(Error::ConfigError("at least one port is required".into()));
+        }
+
+        let mut addresses = HashSet::new();
+        for address in &self.addresses {
+            if !addresses.insert(address) {
+                return Err(Error::ConfigError(format!(
+                    "duplicate address `{}`",
+                    address
+                )));
+            }
+        }
+
+        let mut ports = HashSet::new();
+        for port in &self.ports {
+            if !ports.insert(port) {
+                return Err(Error::ConfigError(format!("duplicate port `{}`", port)));
+            }
+        }
+
+        if let BindPolicy::Ports(ref required) = self.bind_policy {
+            if required.is_empty() {
+                return Err(Error::ConfigError(
+                    "bind_policy must list at least one port".into(),
+                ));
+            }
+
+            for port in required {
+                if !ports.contains(port) {
+                    return Err(Error::ConfigError(format!(
+                        "bind_policy requires port `{}`, which is not in `ports`",
+                        port
+                    )));
+                }
+            }
+        }
+
+        let mut interfaces = HashSet::new();
+        for interface in &self.interfaces {
+            if !interfaces.insert(interface) {
+                return Err(Error::ConfigError(format!(
+                    "duplicate interface `{}`",
+                    interface
+                )));
+            }
+        }
+
+        for (name, limit) in &[
+            ("rate_limit", self.rate_limit),
+            ("source_rate_limit", self.source_rate_limit),
+        ] {
+            if let Some(limit) = limit {
+                if limit.rate == 0 || limit.burst == Some(0) {
+                    return Err(Error::ConfigError(format!(
+                        "{} must allow at least 1 packet",
+                        name
+                    )));
+                }
+            }
+        }
+
+        if self.rate_limit_summary_interval == Duration::from_secs(0) {
+            return Err(Error::ConfigError(
+                "rate_limit_summary_interval must be at least 1 second".into(),
+            ));
+        }
+
+        if self.max_concurrent_commands == Some(0) {
+            return Err(Error::ConfigError(
+                "max_concurrent_commands must be at least 1".into(),
+            ));
+        }
+
+        if self.hooks.is_empty() {
+            return Err(Error::ConfigError("at least one hook is required".into()));
+        }
+
+        let mut names = HashSet::new();
+        for hook in &self.hooks {
+            if !names.insert(&hook.name) {
+                return Err(Error::ConfigError(format!(
+                    "duplicate hook name `{}`",
+                    hook.name
+                )));
+            }
+
+            if hook.command.is_empty() {
+                return Err(Error::ConfigError(format!(
+                    "hook `{}` has an empty command",
+                    hook.name
+                )));
+            }
+
+            for interface in &hook.interfaces {
+                if !interfaces.is_empty() && !interfaces.contains(interface) {
+                    return Err(Error::ConfigError(format!(
+                        "hook `{}` is restricted to interface `{}`, which is not in `interfaces`",
+                        hook.name, interface
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: Config = toml::from_str(s).map_err(Error::ConfigParseError)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+/// The default for [`Config::addresses`]: the IPv4 and IPv6 unspecified
+/// addresses.
+pub fn default_addresses() -> Vec<IpAddr> {
+    vec![Ipv4Addr::UNSPECIFIED.into(), Ipv6Addr::UNSPECIFIED.into()]
+}
+
+/// The default for [`Config::ports`].
+///
+/// Wake-on-LAN "magic packets" are sent on UDP ports 0, 7, or 9 *or* as an
+/// Ethernet packet with EtherType `0x0842`.
+///
+/// See [the Wikipedia article][wiki] for more information.
+///
+/// [wiki]: https://en.wikipedia.org/wiki/Wake-on-LAN#Magic_packet
+pub fn default_ports() -> Vec<u16> {
+    vec![0, 7, 9]
+}
+
+/// The default for [`Config::bind_policy`].
+pub fn default_bind_policy() -> BindPolicy {
+    BindPolicy::All
+}
+
+/// The default for [`Config::ethernet`].
+fn default_ethernet() -> bool {
+    true
+}
+
+/// The default for [`Config::shutdown_grace_period`].
+pub fn default_shutdown_grace_period() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// The default for [`Hook::concurrency`].
+pub fn default_concurrency() -> ConcurrencyPolicy {
+    ConcurrencyPolicy::Parallel
+}
+
+/// The default for [`Config::deduplication_window`]: half a second.
+pub fn default_deduplication_window() -> Duration {
+    Duration::from_millis(500)
+}
+
+/// The default for [`Config::rate_limit_summary_interval`]: one minute.
+pub fn default_rate_limit_summary_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// Deserialize a value from a string with its `FromStr` implementation.
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(de::Error::custom)
+}
+
+/// Deserialize an optional value from a string with its `FromStr` implementation.
+fn option_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    from_str(deserializer).map(Some)
+}
+
+/// Deserialize a duration from a number of seconds.
+fn seconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+/// Deserialize a duration from a number of milliseconds.
+fn milliseconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+/// Deserialize an optional duration from a number of seconds.
+fn option_seconds<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    seconds(deserializer).map(Some)
+}
+
+/// Deserialize a list of values from strings with their `FromStr` implementation.
+fn vec_from_str<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+/// A hook for `aa:bb:cc:dd:ee:ff` that runs `true` with the default settings,
+/// for tests to adjust.
+pub fn test_hook(name: &str) -> Hook {
+    Hook {
+        name: name.into(),
+        mac_address: MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff),
+        password: None,
+        command: vec!["true".parse().unwrap()],
+        timeout: None,
+        cooldown: None,
+        concurrency: default_concurrency(),
+        interfaces: vec![],
+        destination_types: vec![],
+        allow_sources: vec![],
+        deny_sources: vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{str::FromStr, time::Duration};
+
+    #[test]
+    fn test_parse() {
+        let config = Config::from_str(
+            r#"
+            addresses = ["192.168.1.2", "fd00::1"]
+            ports = [9, 4343]
+            bind_policy = [9]
+            interfaces = ["br-vm", "br-lab"]
+            shutdown_grace_period = 5
+            scan_payloads = true
+            deduplication_window = 250
+            allow_sources = ["192.168.1.0/24"]
+            deny_sources = ["192.168.1.13", "fd00::/8"]
+            rate_limit = { rate = 100, burst = 200 }
+            source_rate_limit = { rate = 5 }
+            user = "nobody"
+
+            [[hook]]
+            name = "vm1"
+            mac_address = "aa:bb:cc:dd:ee:01"
+            command = ["systemctl", "start", "vm1.service"]
+
+            [[hook]]
+            name = "vm2"
+            mac_address = "aabb.ccdd.ee02"
+            password = "192.168.1.1"
+            command = ["systemctl", "start", "{hook}.service"]
+            timeout = 30
+            cooldown = 10
+            concurrency = "queue"
+            interfaces = ["br-vm"]
+            destination_types = ["broadcast", "subnet-broadcast"]
+            allow_sources = ["192.168.1.0/28"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                addresses: vec!["192.168.1.2".parse().unwrap(), "fd00::1".parse().unwrap(),],
+                ports: vec![9, 4343],
+                bind_policy: BindPolicy::Ports(vec![9]),
+                interfaces: vec!["br-vm".into(), "br-lab".into()],
+                ethernet: true,
+                scan_payloads: true,
+                max_concurrent_commands: None,
+                shutdown_grace_period: Duration::from_secs(5),
+                deduplication_window: Duration::from_millis(250),
+                allow_sources: vec!["192.168.1.0/24".parse().unwrap()],
+                deny_sources: vec!["192.168.1.13".parse().unwrap(), "fd00::/8".parse().unwrap(),],
+                rate_limit: Some(RateLimit {
+                    rate: 100,
+                    burst: Some(200),
+                }),
+                source_rate_limit: Some(RateLimit {
+                    rate: 5,
+                    burst: None,
+                }),
+                rate_limit_summary_interval: Duration::from_secs(60),
+                user: Some("nobody".into()),
+                group: None,
+                hooks: vec![
+                    Hook {
+                        name: "vm1".into(),
+                        mac_address: MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01),
+                        password: None,
+                        command: vec![
+                            "systemctl".parse().unwrap(),
+                            "start".parse().unwrap(),
+                            "vm1.service".parse().unwrap(),
+                        ],
+                        timeout: None,
+                        cooldown: None,
+                        concurrency: ConcurrencyPolicy::Parallel,
+                        interfaces: vec![],
+                        destination_types: vec![],
+                        allow_sources: vec![],
+                        deny_sources: vec![],
+                    },
+                    Hook {
+                        name: "vm2".into(),
+                        mac_address: MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x02),
+                        password: Some("192.168.1.1".parse().unwrap()),
+                        command: vec![
+                            "systemctl".parse().unwrap(),
+                            "start".parse().unwrap(),
+                            "{hook}.service".parse().unwrap(),
+                        ],
+                        timeout: Some(Duration::from_secs(30)),
+                        cooldown: Some(Duration::from_secs(10)),
+                        concurrency: ConcurrencyPolicy::Queue,
+                        interfaces: vec!["br-vm".into()],
+                        destination_types: vec![
+                            DestinationType::Broadcast,
+                            DestinationType::SubnetBroadcast,
+                        ],
+                        allow_sources: vec!["192.168.1.0/28".parse().unwrap()],
+                        deny_sources: vec![],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_matches!(
+            Config::from_str("ethernet = false"),
+            Err(Error::ConfigParseError(..))
+        );
+
+        assert_matches!(Config::from_str("hook = []"), Err(Error::ConfigError(..)));
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee"
+                command = ["true"]
+                "#
+            ),
+            Err(Error::ConfigParseError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:ff"
+                command = []
+                "#
+            ),
+            Err(Error::ConfigError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:02"
+                command = ["true"]
+                "#
+            ),
+            Err(Error::ConfigError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                unknown = 1
+                "#
+            ),
+            Err(Error::ConfigParseError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["echo", "{unknown}"]
+                "#
+            ),
+            Err(Error::ConfigParseError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                ports = []
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                "#
+            ),
+            Err(Error::ConfigError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                addresses = ["::", "::"]
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                "#
+            ),
+            Err(Error::ConfigError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                addresses = ["localhost"]
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                "#
+            ),
+            Err(Error::ConfigParseError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                interfaces = ["br-vm"]
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                interfaces = ["eth0"]
+                "#
+            ),
+            Err(Error::ConfigError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                destination_types = ["anycast"]
+                "#
+            ),
+            Err(Error::ConfigParseError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                deny_sources = ["192.168.1.0/33"]
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                "#
+            ),
+            Err(Error::ConfigParseError(..))
+        );
+
+        assert_matches!(
+            Config::from_str(
+                r#"
+                source_rate_limit = { rate = 0 }
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                "#
+            ),
+            Err(Error::ConfigError(..))
+        );
+
+        let with_bind_policy = |bind_policy: &str| {
+            Config::from_str(&format!(
+                r#"
+                ports = [7, 9]
+                bind_policy = {}
+
+                [[hook]]
+                name = "vm1"
+                mac_address = "aa:bb:cc:dd:ee:01"
+                command = ["true"]
+                "#,
+                bind_policy
+            ))
+        };
+
+        assert_matches!(
+            with_bind_policy(r#""some""#),
+            Err(Error::ConfigParseError(..))
+        );
+        assert_matches!(with_bind_policy(r#""9""#), Err(Error::ConfigParseError(..)));
+        assert_matches!(with_bind_policy("[5]"), Err(Error::ConfigError(..)));
+        assert_matches!(with_bind_policy("[]"), Err(Error::ConfigError(..)));
+        assert_matches!(with_bind_policy(r#""any""#), Ok(..));
+    }
+
+    #[test]
+    fn test_bind_policy() {
+        assert_eq!(BindPolicy::from_str("all").unwrap(), BindPolicy::All);
+        assert_eq!(BindPolicy::from_str("any").unwrap(), BindPolicy::Any);
+        assert_eq!(
+            BindPolicy::from_str("7, 9").unwrap(),
+            BindPolicy::Ports(vec![7, 9])
+        );
+        assert_matches!(
+            BindPolicy::from_str("some"),
+            Err(Error::BindPolicyParseError(ref s)) if s == "some"
+        );
+
+        assert!(!BindPolicy::All.tolerates(9));
+        assert!(BindPolicy::Any.tolerates(9));
+        assert!(!BindPolicy::Ports(vec![9]).tolerates(9));
+        assert!(BindPolicy::Ports(vec![9]).tolerates(0));
+    }
+}
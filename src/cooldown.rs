@@ -0,0 +1,274 @@
+//! Debouncing of repeated wake-on-LAN packets.
+//!
+//! Senders usually transmit several packets for a single wake request (and we
+//! listen on several ports), so a hook with a cooldown will only be triggered
+//! once per cooldown window. A hook with a cooldown and the `parallel`
+//! concurrency policy is also not triggered while a previous invocation of its
+//! command is still running; for the other policies, a trigger after the window
+//! is left to the hook's [`ConcurrencyPolicy`] (e.g., to be queued).
+//!
+//! Cooldowns are tracked per hook and target MAC address: packets for one MAC
+//! address do not suppress packets for another, and if several hooks are
+//! configured for the same MAC address, each has its own cooldown window.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clock::elapsed;
+use config::{ConcurrencyPolicy, Hook};
+use mac::MacAddress;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// Why a hook was not triggered.
+pub enum Suppression {
+    /// A previous invocation of the hook's command is still running and the
+    /// hook's concurrency policy is [`ConcurrencyPolicy::Parallel`].
+    Running,
+
+    /// The hook was triggered too recently.
+    CoolingDown {
+        /// How long until the hook may be triggered again.
+        remaining: Duration,
+    },
+}
+
+#[derive(Debug)]
+/// The cooldown state of a single hook and MAC address.
+struct State {
+    /// When the hook was last triggered.
+    last_triggered: Instant,
+
+    /// Whether or not the command triggered at `last_triggered` is still
+    /// running.
+    running: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+/// The cooldown state of every hook, keyed by hook name and target MAC address.
+///
+/// Hooks without a cooldown are not tracked.
+pub struct Cooldowns(Arc<Mutex<HashMap<(String, MacAddress), State>>>);
+
+impl Cooldowns {
+    /// Attempt to trigger the given hook for a packet to `mac_address` at time
+    /// `now`.
+    ///
+    /// If the hook has a cooldown and may be triggered, a [`CooldownGuard`] is
+    /// returned that must be held until the hook's command finishes.
+    pub fn trigger(
+        &self,
+        hook: &Hook,
+        mac_address: MacAddress,
+        now: Instant,
+    ) -> Result<Option<CooldownGuard>, Suppression> {
+        let cooldown = match hook.cooldown {
+            Some(cooldown) => cooldown,
+            None => return Ok(None),
+        };
+
+        let key = (hook.name.clone(), mac_address);
+        let mut states = self.0.lock().unwrap();
+
+        if let Some(state) = states.get(&key) {
+            if state.running && hook.concurrency == ConcurrencyPolicy::Parallel {
+                return Err(Suppression::Running);
+            }
+
+            let elapsed = elapsed(state.last_triggered, now);
+            if elapsed < cooldown {
+                return Err(Suppression::CoolingDown {
+                    remaining: cooldown - elapsed,
+                });
+            }
+        }
+
+        states.insert(
+            key.clone(),
+            State {
+                last_triggered: now,
+                running: true,
+            },
+        );
+
+        Ok(Some(CooldownGuard {
+            cooldowns: self.clone(),
+            key,
+        }))
+    }
+}
+
+#[derive(Debug)]
+/// A guard that marks a hook's command as running until it is dropped.
+pub struct CooldownGuard {
+    cooldowns: Cooldowns,
+    key: (String, MacAddress),
+}
+
+impl Drop for CooldownGuard {
+    fn drop(&mut self) {
+        if let Some(state) = self.cooldowns.0.lock().unwrap().get_mut(&self.key) {
+            state.running = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use config::test_hook;
+
+    #[test]
+    fn test_no_cooldown() {
+        let cooldowns = Cooldowns::default();
+        let hook = test_hook("vm");
+        let mac = hook.mac_address;
+        let now = Instant::now();
+
+        assert_matches!(cooldowns.trigger(&hook, mac, now), Ok(None));
+        assert_matches!(cooldowns.trigger(&hook, mac, now), Ok(None));
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let cooldowns = Cooldowns::default();
+        let hook = Hook {
+            cooldown: Some(Duration::from_secs(10)),
+            ..test_hook("vm")
+        };
+        let other_hook = Hook {
+            cooldown: Some(Duration::from_secs(10)),
+            ..test_hook("other")
+        };
+        let mac = hook.mac_address;
+        let now = Instant::now();
+
+        let guard = cooldowns.trigger(&hook, mac, now);
+        assert_matches!(guard, Ok(Some(_)));
+
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, mac, now + Duration::from_secs(1))
+                .err(),
+            Some(Suppression::Running)
+        );
+        // Hooks for the same MAC address have separate cooldowns.
+        assert_matches!(cooldowns.trigger(&other_hook, mac, now), Ok(Some(_)));
+
+        drop(guard);
+
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, mac, now + Duration::from_secs(4))
+                .err(),
+            Some(Suppression::CoolingDown {
+                remaining: Duration::from_secs(6)
+            })
+        );
+
+        assert_matches!(
+            cooldowns.trigger(&hook, mac, now + Duration::from_secs(10)),
+            Ok(Some(_))
+        );
+    }
+
+    #[test]
+    fn test_cooldown_queue() {
+        let cooldowns = Cooldowns::default();
+        let hook = Hook {
+            cooldown: Some(Duration::from_secs(10)),
+            concurrency: ConcurrencyPolicy::Queue,
+            ..test_hook("vm")
+        };
+        let mac = hook.mac_address;
+        let now = Instant::now();
+
+        let guard = cooldowns.trigger(&hook, mac, now);
+        assert_matches!(guard, Ok(Some(_)));
+
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, mac, now + Duration::from_secs(4))
+                .err(),
+            Some(Suppression::CoolingDown {
+                remaining: Duration::from_secs(6)
+            })
+        );
+
+        // Once the window has passed, the scheduler decides what to do with the
+        // running command, even if it is still running.
+        let queued = cooldowns.trigger(&hook, mac, now + Duration::from_secs(10));
+        assert_matches!(queued, Ok(Some(_)));
+
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, mac, now + Duration::from_secs(11))
+                .err(),
+            Some(Suppression::CoolingDown {
+                remaining: Duration::from_secs(9)
+            })
+        );
+    }
+
+    #[test]
+    fn test_cooldown_mac_addresses() {
+        let cooldowns = Cooldowns::default();
+        let hook = Hook {
+            cooldown: Some(Duration::from_secs(10)),
+            ..test_hook("vm")
+        };
+        let mac = hook.mac_address;
+        let other_mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01);
+        let now = Instant::now();
+
+        let guard = cooldowns.trigger(&hook, mac, now);
+        assert_matches!(guard, Ok(Some(_)));
+
+        // Packets for one MAC address do not suppress packets for another.
+        let other_guard = cooldowns.trigger(&hook, other_mac, now + Duration::from_secs(1));
+        assert_matches!(other_guard, Ok(Some(_)));
+
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, mac, now + Duration::from_secs(2))
+                .err(),
+            Some(Suppression::Running)
+        );
+
+        // Finishing one MAC address's command leaves the other running.
+        drop(guard);
+
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, mac, now + Duration::from_secs(4))
+                .err(),
+            Some(Suppression::CoolingDown {
+                remaining: Duration::from_secs(6)
+            })
+        );
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, other_mac, now + Duration::from_secs(4))
+                .err(),
+            Some(Suppression::Running)
+        );
+
+        drop(other_guard);
+
+        assert_matches!(
+            cooldowns.trigger(&hook, mac, now + Duration::from_secs(10)),
+            Ok(Some(_))
+        );
+        assert_eq!(
+            cooldowns
+                .trigger(&hook, other_mac, now + Duration::from_secs(10))
+                .err(),
+            Some(Suppression::CoolingDown {
+                remaining: Duration::from_secs(1)
+            })
+        );
+    }
+}
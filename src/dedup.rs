@@ -0,0 +1,225 @@
+//! Deduplication of wake-on-LAN packets.
+//!
+//! Senders often transmit the same magic packet to several ports, and over
+//! several interfaces, so a single wake request arrives as a burst of identical
+//! packets within a few milliseconds. Copies that arrive within a short window
+//! of the first do not trigger a hook again, so that one wake request is one
+//! event. This is independent of a hook's cooldown, which applies to separate
+//! wake requests.
+//!
+//! Packets are only recorded for a hook once they have passed its filters
+//! (e.g., its interfaces), so a copy that a hook ignores cannot hide a later
+//! copy that it would accept.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clock::elapsed;
+use config::Hook;
+use event::{Packet, Source};
+use mac::MacAddress;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// What makes two packets copies of each other.
+struct Key {
+    /// The name of the hook the packet was accepted by.
+    hook: String,
+
+    mac_address: MacAddress,
+
+    /// Senders may use a different socket for each port they send to, so the
+    /// source port is ignored.
+    source: Source,
+    payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+/// A record of recently received packets.
+pub struct Deduplicator {
+    /// How long after a packet arrives its copies are dropped.
+    window: Duration,
+
+    /// When each recently accepted packet first arrived.
+    seen: Arc<Mutex<HashMap<Key, Instant>>>,
+}
+
+impl Deduplicator {
+    /// Create a deduplicator with the given window.
+    ///
+    /// If the window is zero, no packets are dropped.
+    pub fn new(window: Duration) -> Self {
+        Deduplicator {
+            window,
+            seen: Default::default(),
+        }
+    }
+
+    /// Record a packet for the given MAC address received at time `now` that
+    /// the given hook has accepted.
+    ///
+    /// If it is a copy of a packet that the hook accepted within the window,
+    /// returns how long ago that packet arrived.
+    pub fn check(
+        &self,
+        hook: &Hook,
+        mac_address: MacAddress,
+        packet: &Packet,
+        now: Instant,
+    ) -> Option<Duration> {
+        if self.window == Duration::from_secs(0) {
+            return None;
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, first_seen| elapsed(*first_seen, now) < self.window);
+
+        let key = Key {
+            hook: hook.name.clone(),
+            mac_address,
+            source: Source::from(&packet.remote),
+            payload: packet.payload.clone(),
+        };
+
+        match seen.entry(key) {
+            Entry::Occupied(entry) => Some(elapsed(*entry.get(), now)),
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use config::test_hook;
+    use event::{DestinationType, Remote};
+
+    fn packet(remote: &str, payload: &[u8]) -> Packet {
+        Packet {
+            payload: payload.to_vec(),
+            remote: Remote::Udp(remote.parse().unwrap()),
+            local_port: Some(9),
+            interface: Some("eth0".into()),
+            interface_index: None,
+            destination: Some("192.168.1.255".parse().unwrap()),
+            destination_type: Some(DestinationType::SubnetBroadcast),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let dedup = Deduplicator::new(Duration::from_millis(500));
+        let hook = test_hook("vm");
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        let first = packet("192.168.1.2:4000", b"packet");
+        let now = Instant::now();
+
+        assert_eq!(dedup.check(&hook, mac, &first, now), None);
+
+        // The same packet from another source port, to another local port.
+        assert_eq!(
+            dedup.check(
+                &hook,
+                mac,
+                &Packet {
+                    local_port: Some(7),
+                    ..packet("192.168.1.2:4001", b"packet")
+                },
+                now + Duration::from_millis(10),
+            ),
+            Some(Duration::from_millis(10))
+        );
+
+        // Different senders, targets, and payloads are not copies.
+        assert_eq!(
+            dedup.check(&hook, mac, &packet("192.168.1.3:4000", b"packet"), now),
+            None
+        );
+        assert_eq!(
+            dedup.check(
+                &hook,
+                MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x00),
+                &first,
+                now
+            ),
+            None
+        );
+        assert_eq!(
+            dedup.check(
+                &hook,
+                mac,
+                &packet("192.168.1.2:4000", b"other packet"),
+                now
+            ),
+            None
+        );
+        assert_eq!(
+            dedup.check(
+                &hook,
+                mac,
+                &Packet {
+                    remote: Remote::Ethernet(MacAddress(1, 2, 3, 4, 5, 6)),
+                    ..first.clone()
+                },
+                now
+            ),
+            None
+        );
+
+        // Each hook sees the packet once.
+        assert_eq!(dedup.check(&test_hook("other"), mac, &first, now), None);
+
+        // Once the window has passed, the packet counts again.
+        assert_eq!(
+            dedup.check(&hook, mac, &first, now + Duration::from_millis(500)),
+            None
+        );
+        assert_eq!(
+            dedup.check(&hook, mac, &first, now + Duration::from_millis(600)),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_check_interface() {
+        let dedup = Deduplicator::new(Duration::from_millis(500));
+        let hook = test_hook("vm");
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        let first = packet("192.168.1.2:4000", b"packet");
+        let now = Instant::now();
+
+        assert_eq!(dedup.check(&hook, mac, &first, now), None);
+
+        // The same packet, received on another interface.
+        assert_eq!(
+            dedup.check(
+                &hook,
+                mac,
+                &Packet {
+                    interface: Some("br-vm".into()),
+                    ..first.clone()
+                },
+                now + Duration::from_millis(1),
+            ),
+            Some(Duration::from_millis(1))
+        );
+    }
+
+    #[test]
+    fn test_disabled() {
+        let dedup = Deduplicator::new(Duration::from_secs(0));
+        let hook = test_hook("vm");
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        let packet = packet("192.168.1.2:4000", b"packet");
+        let now = Instant::now();
+
+        assert_eq!(dedup.check(&hook, mac, &packet, now), None);
+        assert_eq!(dedup.check(&hook, mac, &packet, now), None);
+    }
+}
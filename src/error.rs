@@ -1,8 +1,9 @@
 //! The error types of `wake-on-lan-hook`.
 
-use std::io;
+use std::{io, net::SocketAddr};
 
 use combine::easy;
+use toml;
 
 #[derive(Debug, Fail)]
 /// An error inside of `wake-on-lan-hook`.
@@ -11,20 +12,123 @@ pub enum Error {
     /// An error that occurs when a MAC address cannot be parsed from a string.
     MacParseError(#[cause] easy::Errors<char, String, usize>),
 
+    #[fail(
+        display = "Invalid MAC address format `{}`; expected a notation (colon, dash, dotted, or bare) and/or a case (upper or lower), separated by a comma",
+        _0
+    )]
+    /// An error that occurs when a MAC address format cannot be parsed from a string.
+    MacAddressFormatParseError(String),
+
     #[fail(display = "Could not parse magic packet")]
     /// An error that occurs when a sequence of bytes does not correctly parse as a wake-on-LAN magic packet.
     MagicPacketParseError(#[cause] easy::Errors<u8, String, usize>),
 
     #[fail(
-        display = "Invalid packet length ({}); wake-on-LAN magic packets should be 106 bytes",
+        display = "Invalid packet length ({}); wake-on-LAN magic packets should be 102, 106, or 108 bytes",
         _0
     )]
     /// An error that occurs when a sequence of bytes is the wrong length to be a wake-on-LAN magic packet.
     MagicPacketLengthError(usize),
 
-    #[fail(display = "Could not bind to wake-on-LAN port {}", _0)]
-    /// An error that occurs when wake-on-lan-hook cannot bind to a port.
-    BindError(u16, #[cause] io::Error),
+    #[fail(display = "No wake-on-LAN magic packet found in {} byte payload", _0)]
+    /// An error that occurs when scanning a payload does not find a magic packet's sync stream anywhere in it.
+    MagicPacketNotFoundError(usize),
+
+    #[fail(display = "Invalid SecureOn password: {}", _0)]
+    /// An error that occurs when a SecureOn password cannot be parsed from a string.
+    SecureOnPasswordParseError(String),
+
+    #[fail(
+        display = "Invalid Ethernet frame length ({}); Ethernet frames must be at least 14 bytes",
+        _0
+    )]
+    /// An error that occurs when a sequence of bytes is too short to be an Ethernet frame.
+    EthernetFrameLengthError(usize),
+
+    #[fail(
+        display = "Invalid command template `{}`; literal braces must be written as `{{{{` and `}}}}`",
+        _0
+    )]
+    /// An error that occurs when a command argument cannot be parsed as a template.
+    TemplateParseError(String, #[cause] easy::Errors<char, String, usize>),
+
+    #[fail(
+        display = "Unknown placeholder `{{{}}}` in command template; literal braces must be written as `{{{{` and `}}}}`",
+        _0
+    )]
+    /// An error that occurs when a command argument refers to a placeholder that does not exist.
+    UnknownPlaceholderError(String),
+
+    #[fail(display = "Could not bind to wake-on-LAN address {}", _0)]
+    /// An error that occurs when wake-on-lan-hook cannot bind to a UDP address.
+    BindError(SocketAddr, #[cause] io::Error),
+
+    #[fail(display = "Unknown network interface `{}`", _0)]
+    /// An error that occurs when a configured network interface does not exist.
+    UnknownInterfaceError(String),
+
+    #[fail(display = "Invalid socket activation environment: {}", _0)]
+    /// An error that occurs when the sockets passed by systemd cannot be used.
+    SocketActivationError(String),
+
+    #[fail(display = "Could not listen on socket {} passed by systemd", _0)]
+    /// An error that occurs when a socket passed by systemd is not a bound UDP socket.
+    ActivatedSocketError(String, #[cause] io::Error),
+
+    #[fail(display = "Could not listen for raw Ethernet wake-on-LAN frames")]
+    /// An error that occurs when wake-on-lan-hook cannot open a raw packet socket.
+    EthernetBindError(#[cause] io::Error),
+
+    #[fail(display = "Unknown user `{}`", _0)]
+    /// An error that occurs when the user to run as does not exist.
+    UnknownUserError(String),
+
+    #[fail(display = "Unknown group `{}`", _0)]
+    /// An error that occurs when the group to run as does not exist.
+    UnknownGroupError(String),
+
+    #[fail(display = "Could not drop privileges")]
+    /// An error that occurs when wake-on-lan-hook cannot switch to an unprivileged user or group.
+    PrivilegeDropError(#[cause] io::Error),
+
+    #[fail(display = "Could not drop privileges: {}", _0)]
+    /// An error that occurs when privileges remain after switching to an
+    /// unprivileged user or group.
+    PrivilegeRetainedError(&'static str),
+
+    #[fail(display = "Could not read configuration file {}", _0)]
+    /// An error that occurs when the configuration file cannot be read.
+    ConfigReadError(String, #[cause] io::Error),
+
+    #[fail(display = "Could not parse configuration file")]
+    /// An error that occurs when the configuration file is not valid TOML or
+    /// does not match the expected schema.
+    ConfigParseError(#[cause] toml::de::Error),
+
+    #[fail(display = "Invalid configuration: {}", _0)]
+    /// An error that occurs when the configuration is well-formed but unusable.
+    ConfigError(String),
+
+    #[fail(
+        display = "Invalid concurrency policy `{}`; expected one of parallel, single, queue, or replace",
+        _0
+    )]
+    /// An error that occurs when a concurrency policy cannot be parsed from a string.
+    ConcurrencyPolicyParseError(String),
+
+    #[fail(
+        display = "Invalid bind policy `{}`; expected all, any, or a comma-separated list of ports",
+        _0
+    )]
+    /// An error that occurs when a bind policy cannot be parsed from a string.
+    BindPolicyParseError(String),
+
+    #[fail(
+        display = "Invalid network `{}`; expected an IP address with an optional prefix length (e.g., 192.168.1.0/24)",
+        _0
+    )]
+    /// An error that occurs when a source network cannot be parsed.
+    CidrParseError(String),
 
     #[fail(display = "IO Error: {}", _0)]
     /// A generic IO error.
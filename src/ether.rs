@@ -0,0 +1,383 @@
+//! Definitions and listeners for raw Ethernet wake-on-LAN frames.
+
+use std::{
+    io, mem,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+use libc;
+use mio::{self, unix::EventedFd, Evented, PollOpt, Ready, Token};
+use tokio::{prelude::*, reactor::PollEvented2};
+
+use error::Error;
+use mac::MacAddress;
+
+/// The EtherType of a wake-on-LAN frame.
+pub const ETHERTYPE_WAKE_ON_LAN: u16 = 0x0842;
+
+/// The EtherType of an IEEE 802.1Q VLAN tag.
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+/// The size of an Ethernet header (without a VLAN tag).
+const ETHERNET_HEADER_LEN: usize = 14;
+
+/// The size of an IEEE 802.1Q VLAN tag.
+const VLAN_TAG_LEN: usize = 4;
+
+/// The largest frame we expect to receive, including a VLAN tag.
+const MAX_FRAME_LEN: usize = 1522;
+
+/// The `sll_pkttype` of a frame that was sent by this host.
+///
+/// Packet sockets see outgoing frames as well as incoming ones; this is not
+/// exported by `libc`.
+const PACKET_OUTGOING: u8 = 4;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// A decoded Ethernet frame.
+pub struct EthernetFrame<'a> {
+    /// The destination hardware address.
+    pub destination: MacAddress,
+
+    /// The source hardware address.
+    pub source: MacAddress,
+
+    /// The EtherType of the payload.
+    ///
+    /// If the frame was VLAN tagged, this is the EtherType of the encapsulated
+    /// payload.
+    pub ethertype: u16,
+
+    /// The payload of the frame.
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    /// Decode an Ethernet frame.
+    ///
+    /// Frames with a single IEEE 802.1Q VLAN tag are decoded as if they were
+    /// untagged.
+    pub fn parse(bs: &'a [u8]) -> Result<Self, Error> {
+        if bs.len() < ETHERNET_HEADER_LEN {
+            return Err(Error::EthernetFrameLengthError(bs.len()));
+        }
+
+        let destination = MacAddress(bs[0], bs[1], bs[2], bs[3], bs[4], bs[5]);
+        let source = MacAddress(bs[6], bs[7], bs[8], bs[9], bs[10], bs[11]);
+        let mut ethertype = u16::from(bs[12]) << 8 | u16::from(bs[13]);
+        let mut payload = &bs[ETHERNET_HEADER_LEN..];
+
+        if ethertype == ETHERTYPE_VLAN {
+            if payload.len() < VLAN_TAG_LEN {
+                return Err(Error::EthernetFrameLengthError(bs.len()));
+            }
+
+            ethertype = u16::from(payload[2]) << 8 | u16::from(payload[3]);
+            payload = &payload[VLAN_TAG_LEN..];
+        }
+
+        Ok(EthernetFrame {
+            destination,
+            source,
+            ethertype,
+            payload,
+        })
+    }
+
+    /// Whether or not the frame (or, if it was VLAN tagged, its encapsulated
+    /// payload) is a wake-on-LAN frame.
+    ///
+    /// The listener's socket is bound to the wake-on-LAN EtherType. Linux
+    /// removes any 802.1Q tag before matching a frame against it, so tagged
+    /// wake-on-LAN frames are received without their tag, and tagged frames
+    /// with another EtherType are not received at all. This check only guards
+    /// against frames that did not come through such a socket.
+    pub fn is_wake_on_lan(&self) -> bool {
+        self.ethertype == ETHERTYPE_WAKE_ON_LAN
+    }
+}
+
+/// A raw `AF_PACKET` socket that receives wake-on-LAN frames.
+struct PacketSocket(RawFd);
+
+impl PacketSocket {
+    /// Open a non-blocking packet socket for wake-on-LAN frames.
+    ///
+    /// If `interface_index` is non-zero, only frames received on that interface
+    /// will be delivered.
+    fn bind(interface_index: u32) -> io::Result<Self> {
+        let protocol = ETHERTYPE_WAKE_ON_LAN.to_be();
+
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::c_int::from(protocol),
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = PacketSocket(fd);
+
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as libc::c_ushort;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = interface_index as libc::c_int;
+
+        let rv = unsafe {
+            libc::bind(
+                socket.0,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(socket)
+    }
+
+    /// Receive a single frame into `buf`.
+    ///
+    /// Returns the length of the frame and the link-layer address it was
+    /// received on.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, libc::sockaddr_ll)> {
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        let mut addr_len = mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+
+        let n = unsafe {
+            libc::recvfrom(
+                self.0,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+                &mut addr as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                &mut addr_len,
+            )
+        };
+
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok((n as usize, addr))
+        }
+    }
+}
+
+impl AsRawFd for PacketSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+impl Evented for PacketSocket {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0).deregister(poll)
+    }
+}
+
+/// A stream of raw Ethernet frames with the wake-on-LAN EtherType.
+///
+/// Each item is a complete Ethernet frame, which can be decoded with
+/// [`EthernetFrame::parse()`], and the index of the interface it was received
+/// on.
+pub struct EthernetListener {
+    io: PollEvented2<PacketSocket>,
+}
+
+impl EthernetListener {
+    /// Listen for wake-on-LAN frames.
+    ///
+    /// If `interface_index` is non-zero, only frames received on that interface
+    /// will be delivered. Otherwise, frames from all interfaces are delivered.
+    ///
+    /// This requires `CAP_NET_RAW`.
+    pub fn bind(interface_index: u32) -> io::Result<Self> {
+        PacketSocket::bind(interface_index).map(|socket| EthernetListener {
+            io: PollEvented2::new(socket),
+        })
+    }
+}
+
+impl Stream for EthernetListener {
+    type Item = (Vec<u8>, u32);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Async::NotReady = self.io.poll_read_ready(Ready::readable())? {
+                return Ok(Async::NotReady);
+            }
+
+            let mut buf = vec![0; MAX_FRAME_LEN];
+            match self.io.get_ref().recv_from(&mut buf) {
+                Ok((_, ref addr)) if addr.sll_pkttype == PACKET_OUTGOING => continue,
+                Ok((n, addr)) => {
+                    buf.truncate(n);
+                    return Ok(Async::Ready(Some((buf, addr.sll_ifindex as u32))));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.io.clear_read_ready(Ready::readable())?;
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use nix::net::if_::if_nametoindex;
+
+    use mac::{MacAddress, MagicPacket, ParsedMagicPacket};
+
+    fn make_frame(vlan: bool, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        frame.extend(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+
+        if vlan {
+            frame.extend(&[0x81, 0x00, 0x00, 0x2A]);
+        }
+
+        frame.push((ethertype >> 8) as u8);
+        frame.push(ethertype as u8);
+        frame.extend(payload);
+
+        frame
+    }
+
+    #[test]
+    fn test_parse() {
+        let frame = make_frame(false, ETHERTYPE_WAKE_ON_LAN, &[1, 2, 3]);
+        assert_eq!(
+            EthernetFrame::parse(&frame).unwrap(),
+            EthernetFrame {
+                destination: MacAddress(0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF),
+                source: MacAddress(0x01, 0x02, 0x03, 0x04, 0x05, 0x06),
+                ethertype: ETHERTYPE_WAKE_ON_LAN,
+                payload: &[1, 2, 3],
+            }
+        );
+
+        let frame = make_frame(true, ETHERTYPE_WAKE_ON_LAN, &[1, 2, 3]);
+        assert_eq!(
+            EthernetFrame::parse(&frame).unwrap(),
+            EthernetFrame {
+                destination: MacAddress(0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF),
+                source: MacAddress(0x01, 0x02, 0x03, 0x04, 0x05, 0x06),
+                ethertype: ETHERTYPE_WAKE_ON_LAN,
+                payload: &[1, 2, 3],
+            }
+        );
+
+        let frame = make_frame(true, 0x0800, &[1, 2, 3]);
+        let frame = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(frame.ethertype, 0x0800);
+        assert!(!frame.is_wake_on_lan());
+
+        assert_matches!(
+            EthernetFrame::parse(&[0; 13]),
+            Err(Error::EthernetFrameLengthError(13))
+        );
+
+        let frame = make_frame(true, ETHERTYPE_WAKE_ON_LAN, &[]);
+        assert_matches!(
+            EthernetFrame::parse(&frame[..16]),
+            Err(Error::EthernetFrameLengthError(16))
+        );
+    }
+
+    #[test]
+    fn test_parse_magic_packet() {
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        let frame = make_frame(
+            false,
+            ETHERTYPE_WAKE_ON_LAN,
+            &MagicPacket::new(mac).to_bytes(),
+        );
+
+        let frame = EthernetFrame::parse(&frame).unwrap();
+        assert!(frame.is_wake_on_lan());
+        assert_eq!(ParsedMagicPacket::parse(frame.payload).unwrap().target, mac);
+    }
+
+    /// Send a wake-on-LAN frame over the loopback interface and check that the
+    /// listener receives it.
+    ///
+    /// This requires `CAP_NET_RAW` and is skipped without it.
+    #[test]
+    fn test_loopback() {
+        let lo = if_nametoindex("lo").expect("No loopback interface?");
+
+        let listener = match EthernetListener::bind(lo) {
+            Ok(listener) => listener,
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("Could not bind to loopback interface: {}", e),
+        };
+
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        let frame = make_frame(
+            false,
+            ETHERTYPE_WAKE_ON_LAN,
+            &MagicPacket::new(mac).to_bytes(),
+        );
+
+        let sender = PacketSocket::bind(lo).unwrap();
+        let n = unsafe {
+            libc::send(
+                sender.as_raw_fd(),
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+                0,
+            )
+        };
+        assert_eq!(n, frame.len() as isize);
+
+        let (received, interface_index) = listener
+            .wait()
+            .next()
+            .expect("Stream ended?")
+            .expect("Could not receive frame");
+        assert_eq!(received, frame);
+        assert_eq!(interface_index, lo);
+
+        let received = EthernetFrame::parse(&received).unwrap();
+        assert_eq!(
+            ParsedMagicPacket::parse(received.payload).unwrap().target,
+            mac
+        );
+    }
+}
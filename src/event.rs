@@ -0,0 +1,451 @@
+//! Received packets and the wake events they trigger.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use slog;
+
+use interface;
+use mac::{self, MacAddress, ParsedMagicPacket};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The sender of a wake-on-LAN packet.
+pub enum Remote {
+    /// A UDP packet sent from the given address.
+    Udp(SocketAddr),
+
+    /// A raw Ethernet frame sent from the given hardware address.
+    Ethernet(MacAddress),
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Remote::Udp(ref addr) => addr.fmt(f),
+            Remote::Ethernet(ref mac) => mac.fmt(f),
+        }
+    }
+}
+
+impl slog::Value for Remote {
+    fn serialize(
+        &self,
+        _record: &slog::Record,
+        key: slog::Key,
+        serializer: &mut dyn slog::Serializer,
+    ) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+/// The host a wake-on-LAN packet came from, ignoring the source port.
+pub enum Source {
+    /// A host sending UDP packets from the given IP address.
+    Ip(IpAddr),
+
+    /// A host sending raw Ethernet frames from the given hardware address.
+    Ethernet(MacAddress),
+}
+
+impl<'a> From<&'a Remote> for Source {
+    fn from(remote: &'a Remote) -> Self {
+        match *remote {
+            Remote::Udp(addr) => Source::Ip(addr.ip()),
+            Remote::Ethernet(mac) => Source::Ethernet(mac),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Source::Ip(ref ip) => ip.fmt(f),
+            Source::Ethernet(ref mac) => mac.fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// The kind of address a packet was sent to.
+pub enum DestinationType {
+    /// An address of this host.
+    Unicast,
+
+    /// The limited broadcast address (`255.255.255.255`) or, for raw Ethernet
+    /// frames, the broadcast hardware address.
+    Broadcast,
+
+    /// The broadcast address of one of this host's IPv4 subnets (e.g.,
+    /// `192.168.1.255`).
+    SubnetBroadcast,
+
+    /// A multicast group, such as the IPv6 all-nodes group (`ff02::1`).
+    Multicast,
+}
+
+impl DestinationType {
+    /// Classify the destination IP address of a packet.
+    ///
+    /// If the packet was received on a known interface, only that interface's
+    /// subnets are considered when checking for a subnet-directed broadcast.
+    pub fn of_ip(destination: IpAddr, interface: Option<&str>) -> Self {
+        match destination {
+            IpAddr::V4(addr) if addr == Ipv4Addr::BROADCAST => DestinationType::Broadcast,
+            IpAddr::V4(addr) if addr.is_multicast() => DestinationType::Multicast,
+            IpAddr::V4(addr) if interface::is_subnet_broadcast(addr, interface) => {
+                DestinationType::SubnetBroadcast
+            }
+            IpAddr::V6(addr) if addr.is_multicast() => DestinationType::Multicast,
+            _ => DestinationType::Unicast,
+        }
+    }
+
+    /// Classify the destination hardware address of a raw Ethernet frame.
+    pub fn of_mac(destination: MacAddress) -> Self {
+        if destination == MacAddress(0xff, 0xff, 0xff, 0xff, 0xff, 0xff) {
+            DestinationType::Broadcast
+        } else if destination.0 & 0x01 != 0 {
+            DestinationType::Multicast
+        } else {
+            DestinationType::Unicast
+        }
+    }
+
+    /// The name of the destination type, as used in configuration files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DestinationType::Unicast => "unicast",
+            DestinationType::Broadcast => "broadcast",
+            DestinationType::SubnetBroadcast => "subnet-broadcast",
+            DestinationType::Multicast => "multicast",
+        }
+    }
+}
+
+impl fmt::Display for DestinationType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A packet received by one of the listeners.
+pub struct Packet {
+    /// The payload of the packet.
+    ///
+    /// For raw Ethernet frames, this excludes the Ethernet header.
+    pub payload: Vec<u8>,
+
+    /// The sender of the packet.
+    pub remote: Remote,
+
+    /// The local UDP port the packet was received on.
+    ///
+    /// This is `None` for raw Ethernet frames.
+    pub local_port: Option<u16>,
+
+    /// The network interface the packet was received on, if known.
+    ///
+    /// Until the packet is [classified][Packet::classify], this is only the
+    /// interface the listener is bound to, if any.
+    pub interface: Option<String>,
+
+    /// The index of the interface the packet was received on, if known.
+    ///
+    /// This is resolved to a name when the packet is classified.
+    pub interface_index: Option<u32>,
+
+    /// The IP address the packet was sent to.
+    ///
+    /// This is `None` for raw Ethernet frames.
+    pub destination: Option<IpAddr>,
+
+    /// The kind of address the packet was sent to, if known.
+    ///
+    /// For UDP packets, this is only known once the packet is classified.
+    pub destination_type: Option<DestinationType>,
+}
+
+impl Packet {
+    /// Resolve the name of the interface the packet was received on and the
+    /// kind of IP address it was sent to.
+    ///
+    /// This makes system calls, so it is only done once the packet has passed
+    /// the rate limits and source checks.
+    pub fn classify(&mut self) {
+        if let Some(name) = self.interface_index.take().and_then(interface::name) {
+            self.interface = Some(name);
+        }
+
+        if let Some(destination) = self.destination {
+            self.destination_type = Some(DestinationType::of_ip(
+                destination,
+                self.interface.as_ref().map(AsRef::as_ref),
+            ));
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A valid wake-on-LAN packet that has been matched to a hook.
+pub struct WakeEvent {
+    /// The magic packet that was received, including the MAC address it was
+    /// for and its SecureOn password, if any.
+    pub magic_packet: ParsedMagicPacket,
+
+    /// The sender of the packet.
+    pub remote: Remote,
+
+    /// The local UDP port the packet was received on, if any.
+    pub local_port: Option<u16>,
+
+    /// The network interface the packet was received on, if known.
+    pub interface: Option<String>,
+
+    /// The IP address the packet was sent to, if any.
+    pub destination: Option<IpAddr>,
+
+    /// The kind of address the packet was sent to, if known.
+    pub destination_type: Option<DestinationType>,
+
+    /// When the packet was received.
+    pub timestamp: SystemTime,
+}
+
+impl WakeEvent {
+    /// Create a wake event for a packet that was just received.
+    pub fn new(packet: &Packet, magic_packet: ParsedMagicPacket) -> Self {
+        WakeEvent {
+            magic_packet,
+            remote: packet.remote,
+            local_port: packet.local_port,
+            interface: packet.interface.clone(),
+            destination: packet.destination,
+            destination_type: packet.destination_type,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    /// When the packet was received, in seconds since the Unix epoch.
+    pub fn unix_timestamp(&self) -> u64 {
+        self.timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// The environment variables that describe this event to the command of
+    /// the named hook.
+    ///
+    /// The following variables are always set:
+    ///
+    /// * `WAKE_ON_LAN_HOOK`: the name of the hook;
+    /// * `WAKE_ON_LAN_MAC_ADDRESS`: the MAC address the packet was for;
+    /// * `WAKE_ON_LAN_TRANSPORT`: either `udp` or `ethernet`;
+    /// * `WAKE_ON_LAN_TIMESTAMP`: when the packet was received, in seconds since
+    ///   the Unix epoch;
+    /// * `WAKE_ON_LAN_SYNC_OFFSET`: the offset of the magic packet in the
+    ///   payload, which is only nonzero if payloads are scanned; and
+    /// * `WAKE_ON_LAN_PAYLOAD`: the whole payload, in hexadecimal.
+    ///
+    /// UDP packets also set `WAKE_ON_LAN_SOURCE_IP`, `WAKE_ON_LAN_SOURCE_PORT`,
+    /// `WAKE_ON_LAN_LOCAL_PORT`, and `WAKE_ON_LAN_DESTINATION_IP`, while raw
+    /// Ethernet frames set `WAKE_ON_LAN_SOURCE_MAC`. When known, the interface
+    /// the packet was received on is set in `WAKE_ON_LAN_INTERFACE` and the
+    /// kind of address it was sent to (`unicast`, `broadcast`,
+    /// `subnet-broadcast`, or `multicast`) in `WAKE_ON_LAN_DESTINATION_TYPE`. If
+    /// the packet included a SecureOn password, it is set in
+    /// `WAKE_ON_LAN_PASSWORD`, and any other bytes that followed the magic
+    /// packet are set in hexadecimal in `WAKE_ON_LAN_TRAILER`.
+    pub fn environment(&self, hook_name: &str) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("WAKE_ON_LAN_HOOK", hook_name.into()),
+            (
+                "WAKE_ON_LAN_MAC_ADDRESS",
+                self.magic_packet.target.to_string(),
+            ),
+            ("WAKE_ON_LAN_TIMESTAMP", self.unix_timestamp().to_string()),
+            (
+                "WAKE_ON_LAN_SYNC_OFFSET",
+                self.magic_packet.offset.to_string(),
+            ),
+            (
+                "WAKE_ON_LAN_PAYLOAD",
+                mac::to_hex(&self.magic_packet.payload),
+            ),
+        ];
+
+        match self.remote {
+            Remote::Udp(addr) => {
+                env.push(("WAKE_ON_LAN_TRANSPORT", "udp".into()));
+                env.push(("WAKE_ON_LAN_SOURCE_IP", addr.ip().to_string()));
+                env.push(("WAKE_ON_LAN_SOURCE_PORT", addr.port().to_string()));
+            }
+
+            Remote::Ethernet(mac) => {
+                env.push(("WAKE_ON_LAN_TRANSPORT", "ethernet".into()));
+                env.push(("WAKE_ON_LAN_SOURCE_MAC", mac.to_string()));
+            }
+        }
+
+        if let Some(port) = self.local_port {
+            env.push(("WAKE_ON_LAN_LOCAL_PORT", port.to_string()));
+        }
+
+        if let Some(ref interface) = self.interface {
+            env.push(("WAKE_ON_LAN_INTERFACE", interface.clone()));
+        }
+
+        if let Some(destination) = self.destination {
+            env.push(("WAKE_ON_LAN_DESTINATION_IP", destination.to_string()));
+        }
+
+        if let Some(destination_type) = self.destination_type {
+            env.push(("WAKE_ON_LAN_DESTINATION_TYPE", destination_type.to_string()));
+        }
+
+        if let Some(ref password) = self.magic_packet.password {
+            env.push(("WAKE_ON_LAN_PASSWORD", password.to_string()));
+        }
+
+        if !self.magic_packet.trailer.is_empty() {
+            env.push((
+                "WAKE_ON_LAN_TRAILER",
+                mac::to_hex(&self.magic_packet.trailer),
+            ));
+        }
+
+        env
+    }
+}
+
+#[cfg(test)]
+/// An event for a magic packet for `aa:bb:cc:dd:ee:ff` that was broadcast over
+/// UDP to port 9 on `eth0`, for tests to adjust.
+pub fn test_event() -> WakeEvent {
+    let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+
+    WakeEvent {
+        magic_packet: ParsedMagicPacket::parse(&mac::MagicPacket::new(mac).to_bytes()).unwrap(),
+        remote: Remote::Udp("192.168.1.2:4000".parse().unwrap()),
+        local_port: Some(9),
+        interface: Some("eth0".into()),
+        destination: Some("192.168.1.255".parse().unwrap()),
+        destination_type: Some(DestinationType::SubnetBroadcast),
+        timestamp: UNIX_EPOCH + ::std::time::Duration::from_secs(1_500_000_000),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Duration;
+
+    use mac::MagicPacket;
+
+    #[test]
+    fn test_environment() {
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        let payload = MagicPacket::new(mac).to_bytes();
+        let event = test_event();
+
+        assert_eq!(
+            event.environment("vm"),
+            vec![
+                ("WAKE_ON_LAN_HOOK", "vm".into()),
+                ("WAKE_ON_LAN_MAC_ADDRESS", "AA:BB:CC:DD:EE:FF".into()),
+                ("WAKE_ON_LAN_TIMESTAMP", "1500000000".into()),
+                ("WAKE_ON_LAN_SYNC_OFFSET", "0".into()),
+                ("WAKE_ON_LAN_PAYLOAD", mac::to_hex(&payload)),
+                ("WAKE_ON_LAN_TRANSPORT", "udp".into()),
+                ("WAKE_ON_LAN_SOURCE_IP", "192.168.1.2".into()),
+                ("WAKE_ON_LAN_SOURCE_PORT", "4000".into()),
+                ("WAKE_ON_LAN_LOCAL_PORT", "9".into()),
+                ("WAKE_ON_LAN_INTERFACE", "eth0".into()),
+                ("WAKE_ON_LAN_DESTINATION_IP", "192.168.1.255".into()),
+                ("WAKE_ON_LAN_DESTINATION_TYPE", "subnet-broadcast".into()),
+            ]
+        );
+
+        let mut payload = vec![0x08, 0x42];
+        payload.extend(
+            MagicPacket::new(mac)
+                .with_password("192.168.1.1".parse().unwrap())
+                .to_bytes(),
+        );
+        payload.extend(&[0, 0]);
+        let event = WakeEvent {
+            magic_packet: ParsedMagicPacket {
+                target: mac,
+                password: Some("192.168.1.1".parse().unwrap()),
+                offset: 2,
+                trailer: vec![0, 0],
+                payload: payload.clone(),
+            },
+            remote: Remote::Ethernet(MacAddress(1, 2, 3, 4, 5, 6)),
+            local_port: None,
+            interface: None,
+            destination: None,
+            destination_type: None,
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_500_000_000),
+        };
+
+        assert_eq!(
+            event.environment("vm"),
+            vec![
+                ("WAKE_ON_LAN_HOOK", "vm".into()),
+                ("WAKE_ON_LAN_MAC_ADDRESS", "AA:BB:CC:DD:EE:FF".into()),
+                ("WAKE_ON_LAN_TIMESTAMP", "1500000000".into()),
+                ("WAKE_ON_LAN_SYNC_OFFSET", "2".into()),
+                ("WAKE_ON_LAN_PAYLOAD", mac::to_hex(&payload)),
+                ("WAKE_ON_LAN_TRANSPORT", "ethernet".into()),
+                ("WAKE_ON_LAN_SOURCE_MAC", "01:02:03:04:05:06".into()),
+                ("WAKE_ON_LAN_PASSWORD", "192.168.1.1".into()),
+                ("WAKE_ON_LAN_TRAILER", "0000".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_destination_type() {
+        assert_eq!(
+            DestinationType::of_ip("255.255.255.255".parse().unwrap(), None),
+            DestinationType::Broadcast
+        );
+        assert_eq!(
+            DestinationType::of_ip("224.0.0.1".parse().unwrap(), None),
+            DestinationType::Multicast
+        );
+        assert_eq!(
+            DestinationType::of_ip("ff02::1".parse().unwrap(), None),
+            DestinationType::Multicast
+        );
+        assert_eq!(
+            DestinationType::of_ip("127.0.0.1".parse().unwrap(), Some("lo")),
+            DestinationType::Unicast
+        );
+        assert_eq!(
+            DestinationType::of_ip("::1".parse().unwrap(), None),
+            DestinationType::Unicast
+        );
+
+        assert_eq!(
+            DestinationType::of_mac(MacAddress(0xff, 0xff, 0xff, 0xff, 0xff, 0xff)),
+            DestinationType::Broadcast
+        );
+        assert_eq!(
+            DestinationType::of_mac(MacAddress(0x01, 0x00, 0x5e, 0x00, 0x00, 0x01)),
+            DestinationType::Multicast
+        );
+        assert_eq!(
+            DestinationType::of_mac(MacAddress(0x02, 0xfc, 0x00, 0x00, 0x00, 0x01)),
+            DestinationType::Unicast
+        );
+    }
+}
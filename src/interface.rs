@@ -0,0 +1,60 @@
+//! Helpers for looking up network interfaces.
+
+use std::{
+    ffi::CStr,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use libc;
+use nix::{ifaddrs::getifaddrs, sys::socket::SockAddr};
+
+/// Look up the name of the interface with the given index.
+///
+/// Returns `None` if there is no such interface.
+pub fn name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Check whether an address is the broadcast address of one of this host's
+/// IPv4 subnets.
+///
+/// If an interface is given, only its subnets are considered.
+pub fn is_subnet_broadcast(addr: Ipv4Addr, interface: Option<&str>) -> bool {
+    let ifaddrs = match getifaddrs() {
+        Ok(ifaddrs) => ifaddrs,
+        Err(..) => return false,
+    };
+
+    ifaddrs
+        .filter(|ifaddr| {
+            interface
+                .map(|interface| ifaddr.interface_name == interface)
+                .unwrap_or(true)
+        })
+        .any(|ifaddr| match ifaddr.broadcast {
+            Some(SockAddr::Inet(broadcast)) => broadcast.to_std().ip() == IpAddr::V4(addr),
+            _ => false,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use nix::net::if_::if_nametoindex;
+
+    #[test]
+    fn test_name() {
+        let lo = if_nametoindex("lo").expect("No loopback interface?");
+        assert_eq!(name(lo), Some("lo".into()));
+        assert_eq!(name(0), None);
+    }
+}
@@ -1,9 +1,10 @@
 //! Definitions and parsers for MAC addresses and wake-on-LAN magic packets.
 
 use combine::{
-    combinator::eof,
+    combinator::{eof, optional, try},
     parser::{
         char::hex_digit,
+        choice::choice,
         item::{token, value},
         range::{range, take},
         repeat::skip_count_min_max,
@@ -12,32 +13,224 @@ use combine::{
     ParseError, Parser, RangeStream,
 };
 
-use std::{fmt, str::FromStr};
+use std::{fmt, net::Ipv4Addr, str::FromStr};
 
 use error::Error;
 
 /// The size of a wake-on-LAN "magic packet."
 const MAGIC_PACKET_LEN: usize = 102;
 
+/// The header of a wake-on-LAN "magic packet."
+const MAGIC_PACKET_HEADER: [u8; 6] = [0xFF; 6];
+
+/// The sizes of a SecureOn password that may follow a magic packet.
+const SECURE_ON_PASSWORD_LENS: [usize; 2] = [4, 6];
+
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 /// A MAC address, represented as a tuple of six of octets.
 pub struct MacAddress(pub u8, pub u8, pub u8, pub u8, pub u8, pub u8);
 
 impl MacAddress {
-    /// Parse a wake-on-LAN magic packet and, if valid, return the MAC address
-    /// that it is for.
-    ///
-    /// See the [`magic_packet()`] parser for details.
+    /// Display the MAC address in the given format.
+    pub fn display(self, format: MacAddressFormat) -> FormattedMacAddress {
+        FormattedMacAddress(self, format)
+    }
+
+    /// The octets of the MAC address.
+    pub fn octets(self) -> [u8; 6] {
+        [self.0, self.1, self.2, self.3, self.4, self.5]
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.display(MacAddressFormat::default()).fmt(f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// How the octets of a MAC address are grouped and separated.
+pub enum Notation {
+    /// Pairs of hexadecimal digits separated by colons (`aa:bb:cc:dd:ee:ff`).
+    Colon,
+
+    /// Pairs of hexadecimal digits separated by dashes (`aa-bb-cc-dd-ee-ff`).
+    Dash,
+
+    /// Groups of four hexadecimal digits separated by dots, as used by Cisco
+    /// (`aabb.ccdd.eeff`).
+    Dotted,
+
+    /// Hexadecimal digits without separators (`aabbccddeeff`).
+    Bare,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// How to display a MAC address.
+///
+/// The default is uppercase with colons (e.g., `AA:BB:CC:DD:EE:FF`). As a
+/// string, a format is a comma-separated list of a notation (`colon`, `dash`,
+/// `dotted`, or `bare`) and a case (`upper` or `lower`), either of which may be
+/// omitted (e.g., `dash,lower`).
+pub struct MacAddressFormat {
+    /// How the octets are grouped and separated.
+    pub notation: Notation,
+
+    /// Whether or not to use lowercase hexadecimal digits.
+    pub lowercase: bool,
+}
+
+impl Default for MacAddressFormat {
+    fn default() -> Self {
+        MacAddressFormat {
+            notation: Notation::Colon,
+            lowercase: false,
+        }
+    }
+}
+
+impl FromStr for MacAddressFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::MacAddressFormatParseError(s.into());
+
+        let mut notation = None;
+        let mut lowercase = None;
+        for option in s.split(',') {
+            let (notation_option, lowercase_option) = match option.trim() {
+                "colon" => (Some(Notation::Colon), None),
+                "dash" => (Some(Notation::Dash), None),
+                "dotted" => (Some(Notation::Dotted), None),
+                "bare" => (Some(Notation::Bare), None),
+                "upper" => (None, Some(false)),
+                "lower" => (None, Some(true)),
+                _ => return Err(err()),
+            };
+
+            if notation_option.is_some() {
+                if notation.is_some() {
+                    return Err(err());
+                }
+                notation = notation_option;
+            }
+
+            if lowercase_option.is_some() {
+                if lowercase.is_some() {
+                    return Err(err());
+                }
+                lowercase = lowercase_option;
+            }
+        }
+
+        let default = MacAddressFormat::default();
+        Ok(MacAddressFormat {
+            notation: notation.unwrap_or(default.notation),
+            lowercase: lowercase.unwrap_or(default.lowercase),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// A MAC address displayed in a particular format.
+///
+/// See [`MacAddress::display`].
+pub struct FormattedMacAddress(MacAddress, MacAddressFormat);
+
+impl fmt::Display for FormattedMacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let FormattedMacAddress(mac, format) = *self;
+
+        for (i, octet) in mac.octets().iter().enumerate() {
+            let separator = match (format.notation, i) {
+                (_, 0) => None,
+                (Notation::Colon, _) => Some(':'),
+                (Notation::Dash, _) => Some('-'),
+                (Notation::Dotted, 2) | (Notation::Dotted, 4) => Some('.'),
+                (Notation::Dotted, _) | (Notation::Bare, _) => None,
+            };
+
+            if let Some(separator) = separator {
+                write!(f, "{}", separator)?;
+            }
+
+            if format.lowercase {
+                write!(f, "{:02x}", octet)?;
+            } else {
+                write!(f, "{:02X}", octet)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// A wake-on-LAN "magic packet."
+///
+/// See the [`magic_packet()`] parser for the format.
+pub struct MagicPacket {
+    /// The MAC address of the machine to wake.
+    pub target: MacAddress,
+
+    /// The SecureOn password that follows the packet, if any.
+    pub password: Option<SecureOnPassword>,
+}
+
+impl MagicPacket {
+    /// Create a magic packet for the given MAC address, without a SecureOn
+    /// password.
+    pub fn new(target: MacAddress) -> Self {
+        MagicPacket {
+            target,
+            password: None,
+        }
+    }
+
+    /// Add a SecureOn password to the magic packet.
+    pub fn with_password(self, password: SecureOnPassword) -> Self {
+        MagicPacket {
+            password: Some(password),
+            ..self
+        }
+    }
+
+    /// Serialize the magic packet: 6 bytes of `0xFF`, 16 repetitions of the
+    /// MAC address, and then the SecureOn password, if any.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let password = self
+            .password
+            .as_ref()
+            .map_or(&[][..], |password| &password.0);
+
+        let mut bs = Vec::with_capacity(MAGIC_PACKET_LEN + password.len());
+        bs.extend(&MAGIC_PACKET_HEADER);
+        for _ in 0..16 {
+            bs.extend(&self.target.octets());
+        }
+        bs.extend(password);
+
+        bs
+    }
+
+    /// Parse a wake-on-LAN magic packet.
     ///
-    pub fn from_magic_packet(bs: &[u8]) -> Result<Self, Error> {
-        if bs.len() != MAGIC_PACKET_LEN {
+    /// The packet must be exactly 102 bytes, or 106 or 108 bytes with a
+    /// SecureOn password. See the [`magic_packet()`] parser for details.
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, Error> {
+        let valid_len = bs.len() == MAGIC_PACKET_LEN
+            || SECURE_ON_PASSWORD_LENS
+                .iter()
+                .any(|len| bs.len() == MAGIC_PACKET_LEN + len);
+
+        if !valid_len {
             return Err(Error::MagicPacketLengthError(bs.len()));
         }
 
         let stream = State::with_positioner(bs, IndexPositioner::new());
         magic_packet()
             .easy_parse(stream)
-            .map(|(mac, _)| mac)
+            .map(|(result, _)| result)
             .map_err(|e| {
                 Error::MagicPacketParseError(e.map_range(|r| {
                     let bytes_as_str = r
@@ -51,13 +244,140 @@ impl MacAddress {
     }
 }
 
-impl fmt::Display for MacAddress {
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// A wake-on-LAN magic packet as it was received.
+pub struct ParsedMagicPacket {
+    /// The MAC address of the machine to wake.
+    pub target: MacAddress,
+
+    /// The SecureOn password that followed the sync stream, if any.
+    pub password: Option<SecureOnPassword>,
+
+    /// The offset of the sync stream (6 bytes of `0xFF` followed by 16
+    /// repetitions of the MAC address) in the payload.
+    pub offset: usize,
+
+    /// The bytes that followed the sync stream, other than the SecureOn
+    /// password.
+    pub trailer: Vec<u8>,
+
+    /// The payload the magic packet was parsed from.
+    pub payload: Vec<u8>,
+}
+
+impl ParsedMagicPacket {
+    /// Parse a payload that is exactly a wake-on-LAN magic packet.
+    ///
+    /// See [`MagicPacket::from_bytes`] for details.
+    pub fn parse(payload: &[u8]) -> Result<Self, Error> {
+        let packet = MagicPacket::from_bytes(payload)?;
+
+        Ok(ParsedMagicPacket {
+            target: packet.target,
+            password: packet.password,
+            offset: 0,
+            trailer: Vec::new(),
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Search a payload for the sync stream of a wake-on-LAN magic packet.
+    ///
+    /// Unlike [`ParsedMagicPacket::parse`], the sync stream may be preceded by
+    /// other data, such as a header added by the sending tool, and followed by
+    /// padding. If it is followed by exactly 4 or 6 bytes, those are taken to
+    /// be a SecureOn password. If there are several sync streams, the first is
+    /// returned.
+    ///
+    /// Returns [`Error::MagicPacketNotFoundError`] if there is no sync stream.
+    pub fn scan(payload: &[u8]) -> Result<Self, Error> {
+        let last_offset = match payload.len().checked_sub(MAGIC_PACKET_LEN) {
+            Some(last_offset) => last_offset,
+            None => return Err(Error::MagicPacketNotFoundError(payload.len())),
+        };
+
+        let mut found = (0..=last_offset)
+            .filter(|&offset| payload[offset..].starts_with(&MAGIC_PACKET_HEADER))
+            .filter_map(|offset| {
+                // The sync stream is exactly a magic packet without a
+                // password, for the MAC address that follows the header.
+                let candidate = &payload[offset..];
+                let bs = &candidate[MAGIC_PACKET_HEADER.len()..];
+                let target = MacAddress(bs[0], bs[1], bs[2], bs[3], bs[4], bs[5]);
+
+                if candidate.starts_with(&MagicPacket::new(target).to_bytes()) {
+                    Some((target, offset))
+                } else {
+                    None
+                }
+            });
+
+        let first = found
+            .next()
+            .ok_or_else(|| Error::MagicPacketNotFoundError(payload.len()))?;
+
+        // If the sync stream is preceded by more 0xFF bytes and the MAC address
+        // ends in 0xFF, the stream also parses one byte earlier, for a MAC
+        // address starting with 0xFF. Such addresses are multicast addresses,
+        // which cannot be woken, so prefer a stream for any other address.
+        let (target, offset) = if (first.0).0 == 0xFF {
+            found.find(|&(target, _)| target.0 != 0xFF).unwrap_or(first)
+        } else {
+            first
+        };
+
+        let trailer = &payload[offset + MAGIC_PACKET_LEN..];
+        let (password, trailer) = if SECURE_ON_PASSWORD_LENS.contains(&trailer.len()) {
+            (Some(SecureOnPassword(trailer.to_vec())), Vec::new())
+        } else {
+            (None, trailer.to_vec())
+        };
+
+        Ok(ParsedMagicPacket {
+            target,
+            password,
+            offset,
+            trailer,
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// Write bytes as lowercase hexadecimal digits, without separators.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// A SecureOn password.
+///
+/// SecureOn passwords are either 4 or 6 bytes long and are appended to the end
+/// of a magic packet. A 4 byte password is conventionally written like an IPv4
+/// address (e.g., `192.168.1.1`) and a 6 byte password is conventionally
+/// written like a MAC address (e.g., `aa:bb:cc:dd:ee:ff`).
+pub struct SecureOnPassword(Vec<u8>);
+
+impl fmt::Display for SecureOnPassword {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
-            self.0, self.1, self.2, self.3, self.4, self.5
-        )
+        match self.0[..] {
+            [a, b, c, d] => write!(f, "{}.{}.{}.{}", a, b, c, d),
+            [a, b, c, d, e, g] => MacAddress(a, b, c, d, e, g).fmt(f),
+            _ => unreachable!("SecureOn passwords are either 4 or 6 bytes"),
+        }
+    }
+}
+
+impl FromStr for SecureOnPassword {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = Ipv4Addr::from_str(s) {
+            return Ok(SecureOnPassword(addr.octets().to_vec()));
+        }
+
+        MacAddress::from_str(s)
+            .map(|mac| SecureOnPassword(vec![mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]))
+            .map_err(|_| Error::SecureOnPasswordParseError(s.into()))
     }
 }
 
@@ -85,31 +405,51 @@ where
 }
 
 /// Return a parser to parse a MAC address from a string.
+///
+/// The MAC address may be in any [`Notation`] (e.g., `aa:bb:cc:dd:ee:ff`,
+/// `aa-bb-cc-dd-ee-ff`, `aabb.ccdd.eeff`, or `aabbccddeeff`), but the same
+/// separator must be used throughout.
 fn mac_address<I>() -> impl Parser<Input = I, Output = MacAddress>
 where
     I: RangeStream<Item = char>,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
 {
-    (
-        hex_byte().skip(token(':')),
-        hex_byte().skip(token(':')),
-        hex_byte().skip(token(':')),
-        hex_byte().skip(token(':')),
-        hex_byte().skip(token(':')),
-        hex_byte().skip(eof()),
-    )
-        .map(|(a, b, c, d, e, f)| MacAddress(a, b, c, d, e, f))
+    // The remaining five octets, each preceded by the separator.
+    let separated = |separator: char| {
+        (
+            token(separator).with(hex_byte()),
+            token(separator).with(hex_byte()),
+            token(separator).with(hex_byte()),
+            token(separator).with(hex_byte()),
+            token(separator).with(hex_byte()),
+        )
+    };
+
+    // The remaining four octets after the first two, with or without dots.
+    let dotted_or_bare = choice((
+        (
+            token('.').with(hex_byte()),
+            hex_byte(),
+            token('.').with(hex_byte()),
+            hex_byte(),
+        ),
+        (hex_byte(), hex_byte(), hex_byte(), hex_byte()),
+    ));
+
+    let rest = choice((
+        separated(':'),
+        separated('-'),
+        (hex_byte(), dotted_or_bare).map(|(b, (c, d, e, f))| (b, c, d, e, f)),
+    ));
+
+    (hex_byte(), rest.skip(eof())).map(|(a, (b, c, d, e, f))| MacAddress(a, b, c, d, e, f))
 }
 
-/// Return a parser to parse a wake-on-LAN "magic packet".
+/// Return a parser to parse the sync stream of a wake-on-LAN "magic packet":
+/// 6 repetitions of `0xFF` followed by 16 repetitions of a MAC address.
 ///
-/// A "magic packet" is a 102-byte packet that consists of:
-///
-/// * A packet header: 6 repetitions of `0xFF`
-/// * A packet body: 16 repetitions of the MAC address that the packet is designated for.
-///
-///   MAC addresses in the body are encoded sequentially.
-fn magic_packet<'a, I>() -> impl Parser<Input = I, Output = MacAddress> + 'a
+/// Unlike [`magic_packet()`], anything may follow the sync stream.
+fn sync_stream<'a, I>() -> impl Parser<Input = I, Output = MacAddress> + 'a
 where
     I: RangeStream<Item = u8, Range = &'a [u8]> + 'a,
     I::Error: ParseError<I::Item, I::Range, I::Position>,
@@ -123,13 +463,40 @@ where
             .with(value(bytes))
     });
 
-    header
-        .with(body)
+    header.with(body).map(|bytes: &[u8]| {
+        assert!(bytes.len() == 6);
+
+        MacAddress(bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5])
+    })
+}
+
+/// Return a parser to parse a wake-on-LAN "magic packet".
+///
+/// A "magic packet" is a 102-byte packet that consists of:
+///
+/// * A packet header: 6 repetitions of `0xFF`
+/// * A packet body: 16 repetitions of the MAC address that the packet is designated for.
+///
+///   MAC addresses in the body are encoded sequentially.
+///
+/// The packet may optionally be followed by a 4 or 6 byte SecureOn password.
+fn magic_packet<'a, I>() -> impl Parser<Input = I, Output = MagicPacket> + 'a
+where
+    I: RangeStream<Item = u8, Range = &'a [u8]> + 'a,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    let password = optional(choice((try(take(6).skip(eof())), try(take(4).skip(eof())))));
+
+    sync_stream()
+        .and(password)
         .skip(eof().expected("end of packet"))
-        .map(|bytes| {
-            assert!(bytes.len() == 6);
+        .map(|(target, password): (MacAddress, Option<&[u8]>)| {
+            let packet = MagicPacket::new(target);
 
-            MacAddress(bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5])
+            match password {
+                Some(password) => packet.with_password(SecureOnPassword(password.to_vec())),
+                None => packet,
+            }
         })
 }
 
@@ -144,7 +511,7 @@ mod test {
 
     use error::Error;
 
-    fn make_magic_packet(valid_header: bool, macs: Vec<MacAddress>) -> Vec<u8> {
+    fn make_magic_packet(valid_header: bool, macs: &[MacAddress]) -> Vec<u8> {
         let mut packet = Vec::with_capacity(102);
 
         packet.extend(iter::repeat(0xFF).take(6));
@@ -182,10 +549,13 @@ mod test {
 
     #[test]
     fn test_parse() {
-        assert_eq!(
-            MacAddress::from_str("aa:bb:cc:dd:ee:ff").unwrap(),
-            MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff)
-        );
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+
+        assert_eq!(MacAddress::from_str("aa:bb:cc:dd:ee:ff").unwrap(), mac);
+        assert_eq!(MacAddress::from_str("aa-bb-cc-dd-ee-ff").unwrap(), mac);
+        assert_eq!(MacAddress::from_str("aabb.ccdd.eeff").unwrap(), mac);
+        assert_eq!(MacAddress::from_str("aabbccddeeff").unwrap(), mac);
+        assert_eq!(MacAddress::from_str("AABB.CCDD.EEFF").unwrap(), mac);
 
         check_mac_parse_error(
             "aa",
@@ -194,6 +564,8 @@ mod test {
                 errors: vec![
                     easy::Error::Unexpected(easy::Info::Borrowed("end of input".into())),
                     easy::Error::Expected(easy::Info::Token(':')),
+                    easy::Error::Expected(easy::Info::Token('-')),
+                    easy::Error::Expected(easy::Info::Borrowed("hexadecimal digit")),
                 ],
             },
         );
@@ -209,6 +581,42 @@ mod test {
             },
         );
 
+        // Separators may not be mixed.
+        check_mac_parse_error(
+            "aa:bb-cc-dd-ee-ff",
+            easy::Errors {
+                position: 5,
+                errors: vec![
+                    easy::Error::Unexpected(easy::Info::Token('-')),
+                    easy::Error::Expected(easy::Info::Token(':')),
+                ],
+            },
+        );
+
+        check_mac_parse_error(
+            "aabb.ccdd:eeff",
+            easy::Errors {
+                position: 9,
+                errors: vec![
+                    easy::Error::Unexpected(easy::Info::Token(':')),
+                    easy::Error::Expected(easy::Info::Token('.')),
+                ],
+            },
+        );
+
+        assert_matches!(
+            MacAddress::from_str("aabb.ccddeeff"),
+            Err(Error::MacParseError(..))
+        );
+        assert_matches!(
+            MacAddress::from_str("aa:bb:cc:dd:ee"),
+            Err(Error::MacParseError(..))
+        );
+        assert_matches!(
+            MacAddress::from_str("aabbccddeeff00"),
+            Err(Error::MacParseError(..))
+        );
+
         check_mac_parse_error(
             "bb:cc:dd:ee:ff:gg",
             easy::Errors {
@@ -223,7 +631,7 @@ mod test {
 
     /// Check that parsing the given packet results in the given parsing error.
     fn check_magic_packet_parse_error(packet: &[u8], errors: easy::Errors<u8, String, usize>) {
-        let result = MacAddress::from_magic_packet(packet);
+        let result = ParsedMagicPacket::parse(packet);
         assert_matches!(
             result,
             Err(Error::MagicPacketParseError(easy::Errors { .. }))
@@ -240,13 +648,22 @@ mod test {
     }
 
     #[test]
-    fn test_from_magic_packet() {
+    fn test_parse_magic_packet() {
         let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
 
-        let packet = make_magic_packet(true, iter::repeat(mac).take(16).collect());
-        assert_eq!(MacAddress::from_magic_packet(&packet[..]).unwrap(), mac);
+        let packet = MagicPacket::new(mac).to_bytes();
+        assert_eq!(
+            ParsedMagicPacket::parse(&packet[..]).unwrap(),
+            ParsedMagicPacket {
+                target: mac,
+                password: None,
+                offset: 0,
+                trailer: vec![],
+                payload: packet.clone(),
+            }
+        );
 
-        let packet = make_magic_packet(false, iter::repeat(mac).take(16).collect());
+        let packet = make_magic_packet(false, &[mac; 16]);
         check_magic_packet_parse_error(
             &packet[..],
             easy::Errors {
@@ -260,7 +677,7 @@ mod test {
 
         let packet = make_magic_packet(
             true,
-            iter::repeat(mac)
+            &iter::repeat(mac)
                 .take(15)
                 .chain(iter::once(MacAddress(0, 0, 0, 0, 0, 0)))
                 .collect::<Vec<_>>(),
@@ -278,7 +695,7 @@ mod test {
 
         let packet = make_magic_packet(
             true,
-            iter::repeat(mac)
+            &iter::repeat(mac)
                 .take(6)
                 .chain(iter::repeat(MacAddress(0, 0, 0, 0, 0, 0)).take(10))
                 .collect::<Vec<_>>(),
@@ -294,17 +711,190 @@ mod test {
             },
         );
 
-        let packet = vec![];
         assert_matches!(
-            MacAddress::from_magic_packet(&packet[..]),
+            ParsedMagicPacket::parse(&[]),
             Err(Error::MagicPacketLengthError(0))
         );
 
-        let packet = vec![0; 101];
         assert_matches!(
-            MacAddress::from_magic_packet(&packet[..]),
+            ParsedMagicPacket::parse(&[0; 101]),
             Err(Error::MagicPacketLengthError(101))
         );
+
+        assert_matches!(
+            ParsedMagicPacket::parse(&[0; 104]),
+            Err(Error::MagicPacketLengthError(104))
+        );
+    }
+
+    #[test]
+    fn test_parse_magic_packet_secure_on() {
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+
+        let packet = MagicPacket::new(mac)
+            .with_password("192.168.1.1".parse().unwrap())
+            .to_bytes();
+        let parsed = ParsedMagicPacket::parse(&packet[..]).unwrap();
+        assert_eq!(parsed.target, mac);
+        assert_eq!(
+            parsed.password,
+            Some(SecureOnPassword(vec![192, 168, 1, 1]))
+        );
+        assert_eq!(parsed.trailer, vec![]);
+        assert_eq!(parsed.payload, packet);
+
+        let packet = MagicPacket::new(mac)
+            .with_password("01:02:03:04:05:06".parse().unwrap())
+            .to_bytes();
+        let parsed = ParsedMagicPacket::parse(&packet[..]).unwrap();
+        assert_eq!(parsed.target, mac);
+        assert_eq!(
+            parsed.password,
+            Some(SecureOnPassword(vec![1, 2, 3, 4, 5, 6]))
+        );
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+
+        let bytes = MagicPacket::new(mac).to_bytes();
+        assert_eq!(bytes, make_magic_packet(true, &[mac; 16]));
+
+        let bytes = MagicPacket::new(mac)
+            .with_password("192.168.1.1".parse().unwrap())
+            .to_bytes();
+        assert_eq!(bytes.len(), 106);
+        assert_eq!(&bytes[..102], &make_magic_packet(true, &[mac; 16])[..]);
+        assert_eq!(&bytes[102..], &[192, 168, 1, 1]);
+    }
+
+    #[test]
+    fn test_magic_packet_round_trip() {
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+
+        for packet in &[
+            MagicPacket::new(mac),
+            MagicPacket::new(mac).with_password("192.168.1.1".parse().unwrap()),
+            MagicPacket::new(mac).with_password("01:02:03:04:05:06".parse().unwrap()),
+            MagicPacket::new(MacAddress(0xff, 0xff, 0xff, 0xff, 0xff, 0xff)),
+        ] {
+            assert_eq!(
+                MagicPacket::from_bytes(&packet.to_bytes()).unwrap(),
+                *packet
+            );
+        }
+    }
+
+    #[test]
+    fn test_scan_magic_packet() {
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        let magic_packet = MagicPacket::new(mac).to_bytes();
+
+        assert_eq!(
+            ParsedMagicPacket::scan(&magic_packet[..]).unwrap(),
+            ParsedMagicPacket::parse(&magic_packet[..]).unwrap()
+        );
+
+        // After a header.
+        let mut packet = b"WOL header".to_vec();
+        packet.extend(&magic_packet);
+        assert_eq!(
+            ParsedMagicPacket::scan(&packet[..]).unwrap(),
+            ParsedMagicPacket {
+                target: mac,
+                password: None,
+                offset: 10,
+                trailer: vec![],
+                payload: packet.clone(),
+            }
+        );
+
+        // After a header and with a SecureOn password.
+        packet.extend(&[192, 168, 1, 1]);
+        let parsed = ParsedMagicPacket::scan(&packet[..]).unwrap();
+        assert_eq!(parsed.target, mac);
+        assert_eq!(
+            parsed.password,
+            Some(SecureOnPassword(vec![192, 168, 1, 1]))
+        );
+        assert_eq!(parsed.offset, 10);
+        assert_eq!(parsed.trailer, vec![]);
+
+        // Padded to 144 bytes.
+        let mut packet = magic_packet.clone();
+        packet.resize(144, 0);
+        let parsed = ParsedMagicPacket::scan(&packet[..]).unwrap();
+        assert_eq!(parsed.target, mac);
+        assert_eq!(parsed.password, None);
+        assert_eq!(parsed.offset, 0);
+        assert_eq!(parsed.trailer, vec![0; 42]);
+        assert_eq!(parsed.payload, packet);
+
+        // After extra 0xFF bytes.
+        let mut packet = vec![0xFF; 3];
+        packet.extend(&magic_packet);
+        let parsed = ParsedMagicPacket::scan(&packet[..]).unwrap();
+        assert_eq!(parsed.target, mac);
+        assert_eq!(parsed.offset, 3);
+
+        let broadcast = MacAddress(0xff, 0xff, 0xff, 0xff, 0xff, 0xff);
+        let mut packet = vec![0];
+        packet.extend(
+            MagicPacket::new(broadcast)
+                .with_password("255.255.255.255".parse().unwrap())
+                .to_bytes(),
+        );
+        let parsed = ParsedMagicPacket::scan(&packet[..]).unwrap();
+        assert_eq!(parsed.target, broadcast);
+        assert_eq!(
+            parsed.password,
+            Some(SecureOnPassword(vec![0xff, 0xff, 0xff, 0xff]))
+        );
+        assert_eq!(parsed.offset, 1);
+
+        let mut packet = b"WOL header".to_vec();
+        packet.extend(&magic_packet[..101]);
+        assert_matches!(
+            ParsedMagicPacket::scan(&packet[..]),
+            Err(Error::MagicPacketNotFoundError(111))
+        );
+
+        let packet = make_magic_packet(false, &[mac; 16]);
+        assert_matches!(
+            ParsedMagicPacket::scan(&packet[..]),
+            Err(Error::MagicPacketNotFoundError(102))
+        );
+
+        assert_matches!(
+            ParsedMagicPacket::scan(&[]),
+            Err(Error::MagicPacketNotFoundError(0))
+        );
+    }
+
+    #[test]
+    fn test_parse_secure_on_password() {
+        assert_eq!(
+            SecureOnPassword::from_str("192.168.1.1").unwrap(),
+            SecureOnPassword(vec![192, 168, 1, 1])
+        );
+        assert_eq!(
+            SecureOnPassword::from_str("01:02:03:04:05:06").unwrap(),
+            SecureOnPassword(vec![1, 2, 3, 4, 5, 6])
+        );
+        assert_matches!(
+            SecureOnPassword::from_str("01:02:03:04"),
+            Err(Error::SecureOnPasswordParseError(_))
+        );
+
+        assert_eq!(
+            SecureOnPassword(vec![192, 168, 1, 1]).to_string(),
+            "192.168.1.1"
+        );
+        assert_eq!(
+            SecureOnPassword(vec![1, 2, 3, 4, 5, 6]).to_string(),
+            "01:02:03:04:05:06"
+        );
     }
 
     #[test]
@@ -315,10 +905,50 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_display_format() {
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x0f);
+        let display = |format: &str| mac.display(format.parse().unwrap()).to_string();
+
+        assert_eq!(display("colon"), "AA:BB:CC:DD:EE:0F");
+        assert_eq!(display("lower"), "aa:bb:cc:dd:ee:0f");
+        assert_eq!(display("dash"), "AA-BB-CC-DD-EE-0F");
+        assert_eq!(display("dotted,lower"), "aabb.ccdd.ee0f");
+        assert_eq!(display("lower, bare"), "aabbccddee0f");
+        assert_eq!(display("upper"), mac.to_string());
+
+        for format in &["", "dashed", "dash,dotted", "lower,upper"] {
+            assert_matches!(
+                format.parse::<MacAddressFormat>(),
+                Err(Error::MacAddressFormatParseError(ref s)) if s == format
+            );
+        }
+    }
+
     #[test]
     fn test_round_trip() {
         let s = "AA:BB:CC:DD:EE:FF";
 
         assert_eq!(MacAddress::from_str(s).unwrap().to_string(), s);
+
+        let mac = MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+        for notation in &[
+            Notation::Colon,
+            Notation::Dash,
+            Notation::Dotted,
+            Notation::Bare,
+        ] {
+            for &lowercase in &[false, true] {
+                let format = MacAddressFormat {
+                    notation: *notation,
+                    lowercase,
+                };
+
+                assert_eq!(
+                    MacAddress::from_str(&mac.display(format).to_string()).unwrap(),
+                    mac
+                );
+            }
+        }
     }
 }
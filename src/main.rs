@@ -1,11 +1,18 @@
 #[cfg(test)]
 #[macro_use]
 extern crate assert_matches;
+extern crate bytes;
 extern crate combine;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+extern crate futures;
+extern crate libc;
+extern crate mio;
 extern crate nix;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 #[macro_use]
 extern crate slog;
 extern crate slog_async;
@@ -17,14 +24,30 @@ extern crate structopt_derive;
 extern crate tokio;
 extern crate tokio_process;
 extern crate tokio_signal;
+extern crate toml;
 
+mod acl;
+mod capabilities;
+mod clock;
+mod command;
+mod config;
+mod cooldown;
+mod dedup;
 mod error;
+mod ether;
+mod event;
+mod interface;
 mod mac;
+mod privileges;
+mod ratelimit;
+mod scheduler;
 mod server;
+mod systemd;
+mod template;
+mod udp;
 
-use std::process::exit;
+use std::{net::IpAddr, path::PathBuf, process::exit, time::Duration};
 
-use nix::unistd::getuid;
 use slog::Drain;
 use structopt::StructOpt;
 
@@ -32,15 +55,342 @@ use structopt::StructOpt;
 #[structopt(name = "wake-on-lan-hook")]
 /// Listen for wake-on-LAN packets and execute commands.
 struct Options {
-    #[structopt(name = "MAC", parse(try_from_str), raw(required = "true"))]
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    /// A configuration file that declares the hooks to run.
+    ///
+    /// This cannot be combined with MAC, COMMAND, or --password.
+    config: Option<PathBuf>,
+
+    #[structopt(
+        name = "MAC",
+        parse(try_from_str),
+        raw(required_unless = r#""config""#, conflicts_with = r#""config""#)
+    )]
     /// The MAC address to listen for wake-on-LAN packets for.
-    mac_address: mac::MacAddress,
+    ///
+    /// The address may be written with colons (`aa:bb:cc:dd:ee:ff`), dashes
+    /// (`aa-bb-cc-dd-ee-ff`), dots (`aabb.ccdd.eeff`), or no separators
+    /// (`aabbccddeeff`).
+    mac_address: Option<mac::MacAddress>,
+
+    #[structopt(
+        long = "password",
+        parse(try_from_str),
+        raw(conflicts_with = r#""config""#)
+    )]
+    /// A SecureOn password that wake-on-LAN packets must include.
+    ///
+    /// The password may be given as either 4 bytes in IPv4 address notation
+    /// (e.g., `192.168.1.1`) or 6 bytes in MAC address notation (e.g.,
+    /// `aa:bb:cc:dd:ee:ff`). Packets that do not include a matching password
+    /// will be ignored.
+    password: Option<mac::SecureOnPassword>,
+
+    #[structopt(
+        long = "timeout",
+        raw(value_name = r#""SECONDS""#, conflicts_with = r#""config""#)
+    )]
+    /// How long the command may run before it is terminated.
+    ///
+    /// When the timeout expires, the command and every process it started are
+    /// sent SIGTERM and, if they have not exited 5 seconds later, SIGKILL. If
+    /// unset, the command may run forever.
+    timeout: Option<u64>,
+
+    #[structopt(
+        long = "cooldown",
+        raw(value_name = r#""SECONDS""#, conflicts_with = r#""config""#)
+    )]
+    /// How long to ignore further wake-on-LAN packets after the command has
+    /// been triggered.
+    ///
+    /// Packets are also ignored while the command is still running. If unset,
+    /// every packet triggers the command.
+    cooldown: Option<u64>,
+
+    #[structopt(
+        long = "concurrency",
+        parse(try_from_str),
+        raw(
+            value_name = r#""POLICY""#,
+            possible_values = r#"&["parallel", "single", "queue", "replace"]"#,
+            conflicts_with = r#""config""#
+        )
+    )]
+    /// What to do when a wake-on-LAN packet is received while the command is
+    /// still running.
+    ///
+    /// `parallel` runs another copy of the command, `single` ignores the
+    /// packet, `queue` runs the command again once the running one has
+    /// finished, and `replace` terminates the running command and runs it
+    /// again. Defaults to `parallel`.
+    concurrency: Option<config::ConcurrencyPolicy>,
+
+    #[structopt(long = "max-commands", raw(value_name = r#""COUNT""#))]
+    /// The maximum number of commands that may run at once.
+    ///
+    /// Commands triggered beyond this limit wait for a running command to
+    /// finish. This overrides `max_concurrent_commands` in the configuration
+    /// file. If unset, there is no limit.
+    max_commands: Option<usize>,
+
+    #[structopt(long = "shutdown-grace-period", raw(value_name = r#""SECONDS""#))]
+    /// How long running commands have to finish when the server is stopped.
+    ///
+    /// Commands that are still running afterwards are terminated. This
+    /// overrides `shutdown_grace_period` in the configuration file. Defaults to
+    /// 30 seconds.
+    shutdown_grace_period: Option<u64>,
+
+    #[structopt(
+        long = "address",
+        raw(value_name = r#""ADDRESS""#, number_of_values = "1")
+    )]
+    /// An IP address to listen for UDP wake-on-LAN packets on.
+    ///
+    /// This may be given multiple times. If `::` is given, the IPv6 all-nodes
+    /// multicast group is joined as well. This overrides `addresses` in the
+    /// configuration file. Defaults to `0.0.0.0` and `::`.
+    addresses: Vec<IpAddr>,
+
+    #[structopt(long = "port", raw(value_name = r#""PORT""#, number_of_values = "1"))]
+    /// A UDP port to listen for wake-on-LAN packets on.
+    ///
+    /// This may be given multiple times. This overrides `ports` in the
+    /// configuration file. Defaults to ports 0, 7, and 9.
+    ports: Vec<u16>,
+
+    #[structopt(
+        long = "bind-policy",
+        parse(try_from_str),
+        raw(value_name = r#""POLICY""#)
+    )]
+    /// What to do when some of the UDP addresses and ports cannot be bound.
+    ///
+    /// `all` requires every address and port to be bound, `any` requires at
+    /// least one, and a comma-separated list of ports (e.g., `7,9`) requires
+    /// those ports to be bound on every address. Other failures are logged as
+    /// warnings. This overrides `bind_policy` in the configuration file.
+    /// Defaults to `all`.
+    bind_policy: Option<config::BindPolicy>,
+
+    #[structopt(
+        long = "interface",
+        raw(value_name = r#""INTERFACE""#, number_of_values = "1")
+    )]
+    /// A network interface to listen on.
+    ///
+    /// This may be given multiple times. If given, packets that arrive on other
+    /// interfaces are not received. This overrides `interfaces` in the
+    /// configuration file. Defaults to every interface.
+    interfaces: Vec<String>,
+
+    #[structopt(
+        long = "allow-source",
+        parse(try_from_str),
+        raw(value_name = r#""NETWORK""#, number_of_values = "1")
+    )]
+    /// A network that wake-on-LAN packets must be sent from.
+    ///
+    /// This may be given multiple times, as an IP address with an optional
+    /// prefix length (e.g., `192.168.1.0/24`). Packets from other sources, and
+    /// raw Ethernet frames, are ignored. This overrides `allow_sources` in the
+    /// configuration file.
+    allow_sources: Vec<acl::Cidr>,
+
+    #[structopt(
+        long = "deny-source",
+        parse(try_from_str),
+        raw(value_name = r#""NETWORK""#, number_of_values = "1")
+    )]
+    /// A network that wake-on-LAN packets must not be sent from.
+    ///
+    /// This may be given multiple times and takes precedence over
+    /// --allow-source. This overrides `deny_sources` in the configuration file.
+    deny_sources: Vec<acl::Cidr>,
+
+    #[structopt(long = "rate-limit", raw(value_name = r#""PACKETS""#))]
+    /// The number of packets per second to accept in total.
+    ///
+    /// Packets over the limit are dropped and periodically summarized in the
+    /// log. This overrides `rate_limit` in the configuration file, with a burst
+    /// of one second's worth of packets. If unset, there is no limit.
+    rate_limit: Option<u32>,
+
+    #[structopt(long = "source-rate-limit", raw(value_name = r#""PACKETS""#))]
+    /// The number of packets per second to accept from each source.
+    ///
+    /// Packets over the limit are dropped and periodically summarized in the
+    /// log. This overrides `source_rate_limit` in the configuration file, with a
+    /// burst of one second's worth of packets. If unset, there is no limit.
+    source_rate_limit: Option<u32>,
 
-    #[structopt(name = "COMMAND", raw(required = "true"))]
+    #[structopt(long = "user", raw(value_name = r#""USER""#))]
+    /// The user to switch to once the listeners have been bound.
+    ///
+    /// This may be a name or a numeric ID. Commands are run as this user. This
+    /// overrides `user` in the configuration file.
+    user: Option<String>,
+
+    #[structopt(long = "group", raw(value_name = r#""GROUP""#))]
+    /// The group to switch to once the listeners have been bound.
+    ///
+    /// This may be a name or a numeric ID. Supplementary groups are cleared.
+    /// This overrides `group` in the configuration file. Defaults to the
+    /// primary group of USER.
+    group: Option<String>,
+
+    #[structopt(long = "no-ethernet")]
+    /// Do not listen for raw Ethernet wake-on-LAN frames (EtherType 0x0842).
+    ///
+    /// Only UDP wake-on-LAN packets will be received.
+    no_ethernet: bool,
+
+    #[structopt(long = "scan-payloads")]
+    /// Search payloads for a magic packet.
+    ///
+    /// Packets are accepted if the magic packet's sync stream appears anywhere
+    /// in them, such as after a header or followed by padding, rather than
+    /// only if they are exactly a magic packet.
+    scan_payloads: bool,
+
+    #[structopt(
+        name = "COMMAND",
+        raw(required_unless = r#""config""#, conflicts_with = r#""config""#)
+    )]
     /// The command to execute when a wake-on-LAN packet is received.
+    ///
+    /// Details about the packet are passed to the command in `WAKE_ON_LAN_*`
+    /// environment variables. Arguments may also contain placeholders, such as
+    /// `{mac}`, `{source_ip}`, `{port}`, or `{hook}`, that are replaced with
+    /// those details. MAC addresses may be formatted differently, e.g.,
+    /// `{mac:dash,lower}`.
+    ///
+    /// Literal braces must be written as `{{` and `}}`. Commands written for
+    /// earlier versions that contain braces, such as `${VAR}` in `sh -c`
+    /// scripts or `jq` filters, must double them (e.g., `${{VAR}}`).
     command: Vec<String>,
 }
 
+impl Options {
+    /// Build the server configuration.
+    ///
+    /// If a configuration file was given, it will be loaded. Otherwise, a
+    /// configuration with a single hook will be built from the MAC and COMMAND
+    /// arguments.
+    fn into_config(self) -> Result<config::Config, error::Error> {
+        let mut config = match self.config {
+            Some(path) => config::Config::from_file(&path)?,
+            None => {
+                let mac_address = self.mac_address.expect("MAC is required");
+                let command = self
+                    .command
+                    .iter()
+                    .map(|arg| arg.parse())
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                config::Config {
+                    addresses: config::default_addresses(),
+                    ports: config::default_ports(),
+                    bind_policy: config::default_bind_policy(),
+                    interfaces: Vec::new(),
+                    ethernet: true,
+                    scan_payloads: false,
+                    max_concurrent_commands: None,
+                    shutdown_grace_period: config::default_shutdown_grace_period(),
+                    deduplication_window: config::default_deduplication_window(),
+                    allow_sources: Vec::new(),
+                    deny_sources: Vec::new(),
+                    rate_limit: None,
+                    source_rate_limit: None,
+                    rate_limit_summary_interval: config::default_rate_limit_summary_interval(),
+                    user: None,
+                    group: None,
+                    hooks: vec![config::Hook {
+                        name: mac_address.to_string(),
+                        mac_address,
+                        password: self.password,
+                        command,
+                        timeout: self.timeout.map(Duration::from_secs),
+                        cooldown: self.cooldown.map(Duration::from_secs),
+                        concurrency: self.concurrency.unwrap_or_else(config::default_concurrency),
+                        interfaces: Vec::new(),
+                        destination_types: Vec::new(),
+                        allow_sources: Vec::new(),
+                        deny_sources: Vec::new(),
+                    }],
+                }
+            }
+        };
+
+        if self.no_ethernet {
+            config.ethernet = false;
+        }
+
+        if self.scan_payloads {
+            config.scan_payloads = true;
+        }
+
+        if let Some(max_commands) = self.max_commands {
+            if max_commands == 0 {
+                return Err(error::Error::ConfigError(
+                    "--max-commands must be at least 1".into(),
+                ));
+            }
+
+            config.max_concurrent_commands = Some(max_commands);
+        }
+
+        if let Some(shutdown_grace_period) = self.shutdown_grace_period {
+            config.shutdown_grace_period = Duration::from_secs(shutdown_grace_period);
+        }
+
+        if !self.addresses.is_empty() {
+            config.addresses = self.addresses;
+        }
+
+        if !self.ports.is_empty() {
+            config.ports = self.ports;
+        }
+
+        if let Some(bind_policy) = self.bind_policy {
+            config.bind_policy = bind_policy;
+        }
+
+        if !self.interfaces.is_empty() {
+            config.interfaces = self.interfaces;
+        }
+
+        if !self.allow_sources.is_empty() {
+            config.allow_sources = self.allow_sources;
+        }
+
+        if !self.deny_sources.is_empty() {
+            config.deny_sources = self.deny_sources;
+        }
+
+        if let Some(rate) = self.rate_limit {
+            config.rate_limit = Some(ratelimit::RateLimit { rate, burst: None });
+        }
+
+        if let Some(rate) = self.source_rate_limit {
+            config.source_rate_limit = Some(ratelimit::RateLimit { rate, burst: None });
+        }
+
+        if self.user.is_some() {
+            config.user = self.user;
+        }
+
+        if self.group.is_some() {
+            config.group = self.group;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
 /// The `wake-on-lan-hook` entrypoint.
 ///
 /// [`Options`] will be parsed from the command line arguments and will determine
@@ -57,21 +407,41 @@ fn main() {
         let drain = slog_async::Async::new(drain).build().fuse();
         let log = slog::Logger::root(drain, o!{});
 
-        if !getuid().is_root() {
-            crit!(
-                log,
-                "wake-on-lan-hook listens on privileged ports 0, 7, and 9 and must be run as root."
-            );
-            1
-        } else {
-            match server::run(log.clone(), options.mac_address, options.command) {
-                Ok(_) => {
-                    info!(log, "Server shut down.");
-                    0
-                }
-                Err(e) => {
-                    crit!(log, "An unexpected error occurred"; "error" => %e);
-                    1
+        match (options.into_config(), systemd::listen_fds()) {
+            (Err(e), _) => {
+                crit!(log, "Could not load configuration"; "error" => %e);
+                1
+            }
+
+            (_, Err(e)) => {
+                crit!(log, "Could not use sockets passed by systemd"; "error" => %e);
+                1
+            }
+
+            (Ok(config), Ok(listen_fds)) => {
+                match capabilities::missing(&config, !listen_fds.is_empty()) {
+                    Err(e) => {
+                        crit!(log, "Could not determine capabilities"; "error" => %e);
+                        1
+                    }
+
+                    Ok(ref missing) if !missing.is_empty() => {
+                        for missing in missing {
+                            crit!(log, "{}", missing);
+                        }
+                        1
+                    }
+
+                    Ok(_) => match server::run(log.clone(), config, listen_fds) {
+                        Ok(_) => {
+                            info!(log, "Server shut down.");
+                            0
+                        }
+                        Err(e) => {
+                            crit!(log, "An unexpected error occurred"; "error" => %e);
+                            1
+                        }
+                    },
                 }
             }
         }
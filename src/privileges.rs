@@ -0,0 +1,201 @@
+//! Dropping root privileges once the listeners have been bound.
+
+use std::{ffi::CString, io, mem, ptr};
+
+use libc;
+use nix::unistd::{getgroups, setgid, setgroups, setuid, Gid, Uid};
+
+use error::Error;
+
+/// The initial size of the buffer used for `getpwnam_r` and `getgrnam_r`.
+const LOOKUP_BUFFER_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// An unprivileged identity for the server (and the commands it runs) to
+/// switch to.
+pub struct Identity {
+    /// The user to switch to, if any.
+    pub uid: Option<Uid>,
+
+    /// The group to switch to.
+    pub gid: Gid,
+}
+
+impl Identity {
+    /// Look up the identity for the given user and group.
+    ///
+    /// Each may be given as a name or a numeric ID. If only a user is given,
+    /// the server switches to that user's primary group. If neither is given,
+    /// there is no identity to switch to.
+    pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Option<Self>, Error> {
+        let user = match user {
+            Some(user) => Some(lookup_user(user)?),
+            None => None,
+        };
+
+        let gid = match (group, user) {
+            (Some(group), _) => lookup_group(group)?,
+            (None, Some((_, primary_gid))) => primary_gid,
+            (None, None) => return Ok(None),
+        };
+
+        Ok(Some(Identity {
+            uid: user.map(|(uid, _)| uid),
+            gid,
+        }))
+    }
+
+    /// Switch the server to this identity.
+    ///
+    /// Supplementary groups are cleared first, since that requires root. The
+    /// switch cannot be undone.
+    pub fn switch(&self) -> Result<(), Error> {
+        setgroups(&[]).map_err(nix_to_error)?;
+        setgid(self.gid).map_err(nix_to_error)?;
+
+        if let Some(uid) = self.uid {
+            setuid(uid).map_err(nix_to_error)?;
+
+            // Make sure we cannot regain root.
+            if !uid.is_root() && setuid(Uid::from_raw(0)).is_ok() {
+                return Err(Error::PrivilegeRetainedError(
+                    "root privileges could be regained",
+                ));
+            }
+        }
+
+        if !getgroups().map_err(nix_to_error)?.is_empty() {
+            return Err(Error::PrivilegeRetainedError(
+                "supplementary groups were not cleared",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Look up a user's ID and primary group by name or numeric ID.
+fn lookup_user(user: &str) -> Result<(Uid, Gid), Error> {
+    let name = CString::new(user).map_err(|_| Error::UnknownUserError(user.into()))?;
+
+    let mut buf = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    loop {
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        let rv = match user.parse::<libc::uid_t>() {
+            Ok(uid) => unsafe {
+                libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+            },
+            Err(..) => unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            },
+        };
+
+        match rv {
+            0 if result.is_null() => return Err(Error::UnknownUserError(user.into())),
+            0 => return Ok((Uid::from_raw(passwd.pw_uid), Gid::from_raw(passwd.pw_gid))),
+            libc::ERANGE => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+            e => return Err(Error::Io(io::Error::from_raw_os_error(e))),
+        }
+    }
+}
+
+/// Look up a group's ID by name.
+///
+/// Numeric IDs are used as-is.
+fn lookup_group(group: &str) -> Result<Gid, Error> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(Gid::from_raw(gid));
+    }
+
+    let name = CString::new(group).map_err(|_| Error::UnknownGroupError(group.into()))?;
+
+    let mut buf = vec![0 as libc::c_char; LOOKUP_BUFFER_LEN];
+    loop {
+        let mut entry: libc::group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        let rv = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+
+        match rv {
+            0 if result.is_null() => return Err(Error::UnknownGroupError(group.into())),
+            0 => return Ok(Gid::from_raw(entry.gr_gid)),
+            libc::ERANGE => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+            e => return Err(Error::Io(io::Error::from_raw_os_error(e))),
+        }
+    }
+}
+
+/// Convert a `nix` error from switching identities into an error.
+fn nix_to_error(e: ::nix::Error) -> Error {
+    let e = match e {
+        ::nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        // The other errors are for arguments that could not be passed to the
+        // system call.
+        e => io::Error::new(io::ErrorKind::InvalidInput, e),
+    };
+
+    Error::PrivilegeDropError(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(Identity::resolve(None, None).unwrap(), None);
+
+        assert_eq!(
+            Identity::resolve(Some("root"), None).unwrap(),
+            Some(Identity {
+                uid: Some(Uid::from_raw(0)),
+                gid: Gid::from_raw(0),
+            })
+        );
+        assert_eq!(
+            Identity::resolve(Some("0"), Some("1234")).unwrap(),
+            Some(Identity {
+                uid: Some(Uid::from_raw(0)),
+                gid: Gid::from_raw(1234),
+            })
+        );
+        assert_eq!(
+            Identity::resolve(None, Some("root")).unwrap(),
+            Some(Identity {
+                uid: None,
+                gid: Gid::from_raw(0),
+            })
+        );
+
+        assert_matches!(
+            Identity::resolve(Some("no-such-user"), None),
+            Err(Error::UnknownUserError(ref user)) if user == "no-such-user"
+        );
+        assert_matches!(
+            Identity::resolve(Some("root"), Some("no-such-group")),
+            Err(Error::UnknownGroupError(ref group)) if group == "no-such-group"
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Rate limiting of incoming wake-on-LAN packets.
+//!
+//! Every packet is logged and may start a command, so a misbehaving sender or a
+//! deliberate flood could otherwise fill the logs and exhaust the host. Packets
+//! are limited with token buckets, one for each source and one for all
+//! packets, before anything else is done with them. Dropped packets are not
+//! logged individually; instead they are counted and reported periodically.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clock::elapsed;
+use event::{Remote, Source};
+
+/// The most sources whose buckets are tracked at once.
+///
+/// When this many sources are tracked and their buckets are not yet full again,
+/// packets from new sources are dropped rather than letting a flood with spoofed
+/// source addresses use an unbounded amount of memory.
+const MAX_SOURCES: usize = 65536;
+
+/// How often the buckets of quiet sources may be forgotten to make room for a
+/// new source when [`MAX_SOURCES`] sources are tracked.
+///
+/// Forgetting sources takes time proportional to the number tracked, so doing
+/// it for every packet from a new source would let a flood with spoofed source
+/// addresses take quadratic time.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// A token bucket rate limit.
+pub struct RateLimit {
+    /// How many packets per second are accepted, on average.
+    pub rate: u32,
+
+    #[serde(default)]
+    /// How many packets may be accepted at once after a quiet period.
+    ///
+    /// If unset, this is the same as [`RateLimit::rate`].
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    /// The capacity of the bucket.
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.unwrap_or(self.rate))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// The state of a token bucket.
+struct Bucket {
+    /// How many packets may currently be accepted.
+    tokens: f64,
+
+    /// When the tokens were last refilled.
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Create a full bucket.
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.capacity(),
+            updated: now,
+        }
+    }
+
+    /// Refill the bucket for the time that has passed since it was last
+    /// updated.
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = elapsed(self.updated, now);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        self.tokens = (self.tokens + elapsed * f64::from(limit.rate)).min(limit.capacity());
+        self.updated = now;
+    }
+
+    /// Take a token from the bucket, if there is one.
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket would be full at time `now`, in which case it is the
+    /// same as a new bucket.
+    fn is_full(&self, limit: &RateLimit, now: Instant) -> bool {
+        let mut bucket = *self;
+        bucket.refill(limit, now);
+        bucket.tokens >= limit.capacity()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The limit that a dropped packet exceeded.
+pub enum Limit {
+    /// The limit for packets from a single source.
+    Source,
+
+    /// The limit for all packets.
+    Global,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+/// The packets that were dropped since the last summary.
+pub struct Summary {
+    /// How many packets exceeded the limit for their source.
+    pub source_limited: u64,
+
+    /// How many packets exceeded the limit for all packets.
+    pub global_limited: u64,
+
+    /// How many sources had packets dropped.
+    pub sources: usize,
+
+    /// The source that had the most packets dropped, and how many.
+    pub busiest_source: Option<(Source, u64)>,
+}
+
+#[derive(Debug)]
+/// The mutable state of a [`RateLimiter`].
+struct State {
+    /// The bucket for all packets.
+    global: Option<Bucket>,
+
+    /// The bucket for each recently seen source.
+    sources: HashMap<Source, Bucket>,
+
+    /// How many packets have been dropped from each source since the last
+    /// summary.
+    dropped: HashMap<Source, u64>,
+
+    /// How many packets exceeded the limit for their source since the last
+    /// summary.
+    source_limited: u64,
+
+    /// How many packets exceeded the limit for all packets since the last
+    /// summary.
+    global_limited: u64,
+
+    /// When the buckets of quiet sources were last forgotten to make room for
+    /// a new source.
+    last_pruned: Option<Instant>,
+}
+
+#[derive(Clone, Debug)]
+/// Token bucket rate limits for incoming packets.
+pub struct RateLimiter {
+    /// The limit for all packets.
+    global: Option<RateLimit>,
+
+    /// The limit for packets from each source.
+    per_source: Option<RateLimit>,
+
+    state: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter with the given limits.
+    ///
+    /// If neither limit is given, no packets are dropped.
+    pub fn new(global: Option<RateLimit>, per_source: Option<RateLimit>) -> Self {
+        let now = Instant::now();
+
+        RateLimiter {
+            global,
+            per_source,
+            state: Arc::new(Mutex::new(State {
+                global: global.map(|limit| Bucket::new(&limit, now)),
+                sources: HashMap::new(),
+                dropped: HashMap::new(),
+                source_limited: 0,
+                global_limited: 0,
+                last_pruned: None,
+            })),
+        }
+    }
+
+    /// Record a packet from the given remote received at time `now`.
+    ///
+    /// Returns the limit the packet exceeded if it should be dropped. The
+    /// source limit is checked first, so that a single busy source does not use
+    /// up the global limit.
+    pub fn check(&self, remote: &Remote, now: Instant) -> Result<(), Limit> {
+        if self.global.is_none() && self.per_source.is_none() {
+            return Ok(());
+        }
+
+        let source = Source::from(remote);
+        let mut state = self.state.lock().unwrap();
+
+        let within_source_limit = match self.per_source {
+            Some(ref limit) => state.take_source(source, limit, now),
+            None => true,
+        };
+
+        let result = if !within_source_limit {
+            Err(Limit::Source)
+        } else if !state.take_global(self.global, now) {
+            Err(Limit::Global)
+        } else {
+            Ok(())
+        };
+
+        match result {
+            Err(Limit::Source) => state.source_limited += 1,
+            Err(Limit::Global) => state.global_limited += 1,
+            Ok(()) => return Ok(()),
+        }
+
+        if state.dropped.len() < MAX_SOURCES || state.dropped.contains_key(&source) {
+            *state.dropped.entry(source).or_insert(0) += 1;
+        }
+
+        result
+    }
+
+    /// Summarize the packets dropped since the last summary and reset the
+    /// counts.
+    ///
+    /// Buckets of sources that have been quiet long enough to be full again
+    /// are forgotten. Returns `None` if no packets were dropped.
+    pub fn summary(&self, now: Instant) -> Option<Summary> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(ref limit) = self.per_source {
+            state
+                .sources
+                .retain(|_, bucket| !bucket.is_full(limit, now));
+        }
+
+        if state.source_limited == 0 && state.global_limited == 0 {
+            return None;
+        }
+
+        let summary = Summary {
+            source_limited: state.source_limited,
+            global_limited: state.global_limited,
+            sources: state.dropped.len(),
+            busiest_source: state
+                .dropped
+                .iter()
+                .max_by_key(|&(_, &dropped)| dropped)
+                .map(|(&source, &dropped)| (source, dropped)),
+        };
+
+        state.dropped.clear();
+        state.source_limited = 0;
+        state.global_limited = 0;
+
+        Some(summary)
+    }
+}
+
+impl State {
+    /// Take a token from the bucket for all packets, if there is one.
+    fn take_global(&mut self, limit: Option<RateLimit>, now: Instant) -> bool {
+        match (limit, self.global.as_mut()) {
+            (Some(ref limit), Some(bucket)) => bucket.take(limit, now),
+            _ => true,
+        }
+    }
+
+    /// Take a token from the bucket for the given source, if there is one.
+    fn take_source(&mut self, source: Source, limit: &RateLimit, now: Instant) -> bool {
+        if !self.sources.contains_key(&source) && self.sources.len() >= MAX_SOURCES {
+            let prune = self
+                .last_pruned
+                .map(|last_pruned| elapsed(last_pruned, now) >= PRUNE_INTERVAL)
+                .unwrap_or(true);
+
+            if prune {
+                self.sources.retain(|_, bucket| !bucket.is_full(limit, now));
+                self.last_pruned = Some(now);
+            }
+
+            if self.sources.len() >= MAX_SOURCES {
+                return false;
+            }
+        }
+
+        self.sources
+            .entry(source)
+            .or_insert_with(|| Bucket::new(limit, now))
+            .take(limit, now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    fn udp(s: &str) -> Remote {
+        Remote::Udp(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_per_source() {
+        let limiter = RateLimiter::new(
+            None,
+            Some(RateLimit {
+                rate: 2,
+                burst: Some(3),
+            }),
+        );
+        let remote = udp("192.168.1.2:4000");
+        let now = Instant::now();
+
+        // The burst is accepted, regardless of the source port.
+        assert_eq!(limiter.check(&remote, now), Ok(()));
+        assert_eq!(limiter.check(&udp("192.168.1.2:4001"), now), Ok(()));
+        assert_eq!(limiter.check(&remote, now), Ok(()));
+        assert_eq!(limiter.check(&remote, now), Err(Limit::Source));
+
+        // Other sources have their own buckets.
+        assert_eq!(limiter.check(&udp("192.168.1.3:4000"), now), Ok(()));
+
+        // Tokens are refilled at the given rate.
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check(&remote, later), Ok(()));
+        assert_eq!(limiter.check(&remote, later), Err(Limit::Source));
+        assert_eq!(limiter.check(&remote, later), Err(Limit::Source));
+
+        assert_eq!(
+            limiter.summary(later),
+            Some(Summary {
+                source_limited: 3,
+                global_limited: 0,
+                sources: 1,
+                busiest_source: Some((Source::Ip("192.168.1.2".parse().unwrap()), 3)),
+            })
+        );
+        assert_eq!(limiter.summary(later), None);
+    }
+
+    #[test]
+    fn test_global() {
+        let limiter = RateLimiter::new(
+            Some(RateLimit {
+                rate: 2,
+                burst: None,
+            }),
+            Some(RateLimit {
+                rate: 1,
+                burst: None,
+            }),
+        );
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(&udp("192.168.1.2:4000"), now), Ok(()));
+        assert_eq!(
+            limiter.check(&udp("192.168.1.2:4000"), now),
+            Err(Limit::Source)
+        );
+        assert_eq!(limiter.check(&udp("192.168.1.3:4000"), now), Ok(()));
+        assert_eq!(
+            limiter.check(&udp("192.168.1.4:4000"), now),
+            Err(Limit::Global)
+        );
+
+        let summary = limiter.summary(now).unwrap();
+        assert_eq!(summary.source_limited, 1);
+        assert_eq!(summary.global_limited, 1);
+        assert_eq!(summary.sources, 2);
+    }
+
+    #[test]
+    fn test_forget_quiet_sources() {
+        let limit = RateLimit {
+            rate: 1,
+            burst: Some(2),
+        };
+        let limiter = RateLimiter::new(None, Some(limit));
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(&udp("192.168.1.2:4000"), now), Ok(()));
+        assert_eq!(limiter.state.lock().unwrap().sources.len(), 1);
+
+        assert_eq!(limiter.summary(now + Duration::from_millis(500)), None);
+        assert_eq!(limiter.state.lock().unwrap().sources.len(), 1);
+
+        assert_eq!(limiter.summary(now + Duration::from_secs(1)), None);
+        assert_eq!(limiter.state.lock().unwrap().sources.len(), 0);
+    }
+
+    #[test]
+    fn test_max_sources() {
+        let limit = RateLimit {
+            rate: 2,
+            burst: Some(1),
+        };
+        let limiter = RateLimiter::new(None, Some(limit));
+        let now = Instant::now();
+        let remote = |i: u32| Remote::Udp(SocketAddr::new(Ipv4Addr::from(i).into(), 4000));
+
+        for i in 0..MAX_SOURCES as u32 {
+            assert_eq!(limiter.check(&remote(i), now), Ok(()));
+        }
+
+        // None of the buckets are full yet, so new sources are dropped.
+        let new_source = remote(MAX_SOURCES as u32);
+        assert_eq!(limiter.check(&new_source, now), Err(Limit::Source));
+
+        // The buckets are full again, but they were just pruned, so they are
+        // not pruned for every new source.
+        let later = now + Duration::from_millis(600);
+        assert_eq!(limiter.check(&new_source, later), Err(Limit::Source));
+        assert_eq!(limiter.state.lock().unwrap().sources.len(), MAX_SOURCES);
+
+        let later = now + PRUNE_INTERVAL;
+        assert_eq!(limiter.check(&new_source, later), Ok(()));
+        assert_eq!(limiter.state.lock().unwrap().sources.len(), 1);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let limiter = RateLimiter::new(None, None);
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert_eq!(limiter.check(&udp("192.168.1.2:4000"), now), Ok(()));
+        }
+        assert_eq!(limiter.summary(now), None);
+    }
+}
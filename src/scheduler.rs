@@ -0,0 +1,544 @@
+//! Scheduling of hook commands.
+//!
+//! Every triggered hook is handed to the [`Scheduler`], which decides when (and
+//! whether) its command runs according to the hook's [`ConcurrencyPolicy`] and
+//! the global limit on concurrently running commands.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::sync::oneshot;
+use slog;
+use tokio::{self, prelude::*, timer::Delay};
+
+use command;
+use config::{ConcurrencyPolicy, Hook};
+use cooldown::CooldownGuard;
+use event::WakeEvent;
+
+/// A triggered hook whose command has not yet started.
+struct Pending {
+    log: slog::Logger,
+    hook: Hook,
+    event: WakeEvent,
+
+    /// The hook's cooldown guard, which is held until the command finishes.
+    cooldown_guard: Option<CooldownGuard>,
+}
+
+/// A running command.
+struct Running {
+    id: u64,
+
+    /// Signals the command to terminate.
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Default)]
+/// The commands of a single hook.
+struct HookState {
+    /// The hook's running commands.
+    running: Vec<Running>,
+
+    /// How many of the hook's commands are waiting for a free slot.
+    waiting: usize,
+
+    /// Triggers that are waiting for the hook's running command to finish.
+    queue: VecDeque<Pending>,
+}
+
+impl HookState {
+    /// Whether or not the hook has a command that is running or about to run.
+    fn is_active(&self) -> bool {
+        !self.running.is_empty() || self.waiting > 0
+    }
+}
+
+/// The state of the scheduler.
+struct State {
+    hooks: HashMap<String, HookState>,
+
+    /// The number of running commands.
+    running: usize,
+
+    /// The maximum number of commands that may run at once.
+    max_running: Option<usize>,
+
+    /// Commands that are waiting for a free slot.
+    waiting: VecDeque<Pending>,
+
+    /// The identifier of the next command to start.
+    next_id: u64,
+
+    /// Whether or not the server is shutting down, in which case no further
+    /// commands will be started.
+    shutting_down: bool,
+
+    /// Signalled once no commands are running.
+    idle: Vec<oneshot::Sender<()>>,
+}
+
+#[derive(Clone)]
+/// Runs hook commands according to their concurrency policies.
+pub struct Scheduler(Arc<Mutex<State>>);
+
+impl Scheduler {
+    /// Create a scheduler that will run at most `max_running` commands at once.
+    pub fn new(max_running: Option<usize>) -> Self {
+        Scheduler(Arc::new(Mutex::new(State {
+            hooks: HashMap::new(),
+            running: 0,
+            max_running,
+            waiting: VecDeque::new(),
+            next_id: 0,
+            shutting_down: false,
+            idle: Vec::new(),
+        })))
+    }
+
+    /// Trigger the given hook in response to a wake event.
+    ///
+    /// This must be called from within the tokio runtime, as commands are
+    /// spawned onto it.
+    pub fn trigger(
+        &self,
+        log: slog::Logger,
+        hook: &Hook,
+        event: WakeEvent,
+        cooldown_guard: Option<CooldownGuard>,
+    ) {
+        let pending = Pending {
+            log,
+            hook: hook.clone(),
+            event,
+            cooldown_guard,
+        };
+
+        let mut guard = self.0.lock().unwrap();
+        let state = &mut *guard;
+
+        if state.shutting_down {
+            info!(pending.log, "Ignoring trigger; the server is shutting down");
+            return;
+        }
+
+        let hook_state = state.hooks.entry(hook.name.clone()).or_default();
+
+        match hook.concurrency {
+            ConcurrencyPolicy::Parallel => {}
+
+            ConcurrencyPolicy::Single => {
+                if hook_state.is_active() {
+                    info!(
+                        pending.log,
+                        "Ignoring trigger; the command is already running"
+                    );
+                    return;
+                }
+            }
+
+            ConcurrencyPolicy::Queue => {
+                if hook_state.is_active() || !hook_state.queue.is_empty() {
+                    info!(
+                        pending.log,
+                        "Queueing trigger until the running command finishes";
+                        "queued" => hook_state.queue.len() + 1,
+                    );
+                    hook_state.queue.push_back(pending);
+                    return;
+                }
+            }
+
+            ConcurrencyPolicy::Replace => {
+                if hook_state.waiting > 0 {
+                    let name = &hook.name;
+                    state.waiting.retain(|p| &p.hook.name != name);
+                    let hook_state = state.hooks.get_mut(name).unwrap();
+                    hook_state.waiting = 0;
+                }
+
+                let hook_state = state.hooks.get_mut(&hook.name).unwrap();
+                if !hook_state.running.is_empty() {
+                    info!(pending.log, "Cancelling the running command to replace it");
+
+                    for running in &mut hook_state.running {
+                        if let Some(cancel) = running.cancel.take() {
+                            let _ = cancel.send(());
+                        }
+                    }
+
+                    hook_state.queue.clear();
+                    hook_state.queue.push_back(pending);
+                    return;
+                }
+            }
+        }
+
+        self.start_or_wait(state, pending);
+    }
+
+    /// Stop starting commands and wait for the running commands to finish.
+    ///
+    /// Commands that have not started yet are discarded. Commands that are
+    /// still running after `grace_period` are terminated (see
+    /// [`command::run()`]). A summary of the commands that were interrupted is
+    /// logged once every command has finished.
+    pub fn shutdown(
+        &self,
+        log: slog::Logger,
+        grace_period: Duration,
+    ) -> impl Future<Item = (), Error = ()> {
+        let (running, discarded) = {
+            let mut guard = self.0.lock().unwrap();
+            let state = &mut *guard;
+            state.shutting_down = true;
+
+            let mut discarded = state
+                .waiting
+                .drain(..)
+                .map(|pending| pending.hook.name)
+                .collect::<Vec<_>>();
+            for hook_state in state.hooks.values_mut() {
+                hook_state.waiting = 0;
+                discarded.extend(hook_state.queue.drain(..).map(|pending| pending.hook.name));
+            }
+
+            (state.running, discarded)
+        };
+
+        if running > 0 {
+            info!(
+                log,
+                "Waiting for running commands to finish";
+                "running" => running,
+                "grace_period" => ?grace_period,
+            );
+        }
+
+        let scheduler = self.clone();
+        self.idle()
+            .select2(Delay::new(Instant::now() + grace_period))
+            .then(move |result| {
+                let terminated = match result {
+                    Ok(future::Either::A(..)) | Err(future::Either::A(..)) => Vec::new(),
+                    Ok(future::Either::B(..)) | Err(future::Either::B(..)) => {
+                        let terminated = scheduler.cancel_all();
+                        warn!(
+                            log,
+                            "Terminating commands that did not finish in time";
+                            "hooks" => terminated.join(", "),
+                        );
+                        terminated
+                    }
+                };
+
+                scheduler.idle().map(move |()| {
+                    if terminated.is_empty() && discarded.is_empty() {
+                        info!(log, "All commands finished"; "finished" => running);
+                    } else {
+                        warn!(
+                            log,
+                            "Commands were interrupted by shutdown";
+                            "finished" => running - terminated.len(),
+                            "terminated" => terminated.join(", "),
+                            "discarded" => discarded.join(", "),
+                        );
+                    }
+                })
+            })
+    }
+
+    /// Return a future that resolves once no commands are running.
+    fn idle(&self) -> impl Future<Item = (), Error = ()> {
+        let mut state = self.0.lock().unwrap();
+        if state.running == 0 {
+            return future::Either::A(future::ok(()));
+        }
+
+        let (idle, wait) = oneshot::channel();
+        state.idle.push(idle);
+        future::Either::B(wait.map_err(|_| ()))
+    }
+
+    /// Terminate every running command and return the names of their hooks.
+    fn cancel_all(&self) -> Vec<String> {
+        let mut state = self.0.lock().unwrap();
+        let mut cancelled = Vec::new();
+
+        for (name, hook_state) in &mut state.hooks {
+            for running in &mut hook_state.running {
+                if let Some(cancel) = running.cancel.take() {
+                    let _ = cancel.send(());
+                }
+                cancelled.push(name.clone());
+            }
+        }
+
+        cancelled.sort();
+        cancelled
+    }
+
+    /// Start a command if there is a free slot or wait for one otherwise.
+    fn start_or_wait(&self, state: &mut State, pending: Pending) {
+        let at_capacity = state
+            .max_running
+            .map(|max_running| state.running >= max_running)
+            .unwrap_or(false);
+
+        if at_capacity {
+            info!(
+                pending.log,
+                "Waiting for another command to finish";
+                "max_concurrent_commands" => state.max_running,
+            );
+
+            state
+                .hooks
+                .get_mut(&pending.hook.name)
+                .expect("no state for hook")
+                .waiting += 1;
+            state.waiting.push_back(pending);
+        } else {
+            self.start(state, pending);
+        }
+    }
+
+    /// Start a command.
+    fn start(&self, state: &mut State, pending: Pending) {
+        let id = state.next_id;
+        state.next_id += 1;
+        state.running += 1;
+
+        let (cancel, cancelled) = oneshot::channel();
+        state
+            .hooks
+            .get_mut(&pending.hook.name)
+            .expect("no state for hook")
+            .running
+            .push(Running {
+                id,
+                cancel: Some(cancel),
+            });
+
+        let Pending {
+            log,
+            hook,
+            event,
+            cooldown_guard,
+        } = pending;
+
+        let scheduler = self.clone();
+        tokio::spawn(
+            command::run(log, &hook, &event, cancelled).then(move |result| {
+                drop(cooldown_guard);
+                scheduler.finished(&hook.name, id);
+                result
+            }),
+        );
+    }
+
+    /// Record that a command has finished and start any commands that were
+    /// waiting for it.
+    fn finished(&self, hook_name: &str, id: u64) {
+        let mut guard = self.0.lock().unwrap();
+        let state = &mut *guard;
+        state.running -= 1;
+
+        let next = {
+            let hook_state = state.hooks.get_mut(hook_name).expect("no state for hook");
+            hook_state.running.retain(|running| running.id != id);
+
+            if hook_state.is_active() {
+                None
+            } else {
+                hook_state.queue.pop_front()
+            }
+        };
+
+        if state.shutting_down {
+            if state.running == 0 {
+                for idle in state.idle.drain(..) {
+                    let _ = idle.send(());
+                }
+            }
+
+            return;
+        }
+
+        if let Some(pending) = next {
+            self.start_or_wait(state, pending);
+        }
+
+        while state
+            .max_running
+            .map(|max_running| state.running < max_running)
+            .unwrap_or(true)
+        {
+            let pending = match state.waiting.pop_front() {
+                Some(pending) => pending,
+                None => break,
+            };
+
+            state
+                .hooks
+                .get_mut(&pending.hook.name)
+                .expect("no state for hook")
+                .waiting -= 1;
+            self.start(state, pending);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{env, fs, process};
+
+    use slog::Discard;
+
+    use config::test_hook;
+    use event::test_event;
+
+    /// Trigger each hook in turn (and then shut down, if given a grace period)
+    /// and return what their commands wrote to the
+    /// log file.
+    fn run(
+        test_name: &str,
+        max_running: Option<usize>,
+        hooks: Vec<(ConcurrencyPolicy, &str)>,
+        shutdown_grace_period: Option<Duration>,
+    ) -> String {
+        let path =
+            env::temp_dir().join(format!("wake-on-lan-hook-{}-{}", test_name, process::id()));
+        let path_str = path.to_str().unwrap().to_owned();
+
+        let hooks = hooks
+            .into_iter()
+            .map(|(concurrency, name)| {
+                let script = format!(
+                    "echo {name}: start >> {path}; sleep 0.2; echo {name}: end >> {path}",
+                    name = name,
+                    path = path_str,
+                );
+                Hook {
+                    command: vec![
+                        "sh".parse().unwrap(),
+                        "-c".parse().unwrap(),
+                        script.parse().unwrap(),
+                    ],
+                    concurrency,
+                    ..test_hook(name)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        tokio::run(future::lazy(move || {
+            let log = slog::Logger::root(Discard, o!{});
+            let scheduler = Scheduler::new(max_running);
+            for hook in &hooks {
+                scheduler.trigger(log.clone(), hook, test_event(), None);
+            }
+            if let Some(grace_period) = shutdown_grace_period {
+                tokio::spawn(scheduler.shutdown(log, grace_period));
+            }
+            Ok(())
+        }));
+
+        let output = fs::read_to_string(&path).unwrap_or_default();
+        let _ = fs::remove_file(&path);
+        output
+    }
+
+    #[test]
+    fn test_single() {
+        assert_eq!(
+            run(
+                "single",
+                None,
+                vec![
+                    (ConcurrencyPolicy::Single, "a"),
+                    (ConcurrencyPolicy::Single, "a"),
+                ],
+                None
+            ),
+            "a: start\na: end\n"
+        );
+    }
+
+    #[test]
+    fn test_queue() {
+        assert_eq!(
+            run(
+                "queue",
+                None,
+                vec![
+                    (ConcurrencyPolicy::Queue, "a"),
+                    (ConcurrencyPolicy::Queue, "a"),
+                ],
+                None
+            ),
+            "a: start\na: end\na: start\na: end\n"
+        );
+    }
+
+    #[test]
+    fn test_replace() {
+        // The first command may be cancelled before it has even started.
+        let output = run(
+            "replace",
+            None,
+            vec![
+                (ConcurrencyPolicy::Replace, "a"),
+                (ConcurrencyPolicy::Replace, "a"),
+            ],
+            None,
+        );
+        assert!(output.ends_with("a: start\na: end\n"));
+        assert_eq!(output.matches("end").count(), 1);
+    }
+
+    #[test]
+    fn test_max_running() {
+        assert_eq!(
+            run(
+                "max_running",
+                Some(1),
+                vec![
+                    (ConcurrencyPolicy::Parallel, "a"),
+                    (ConcurrencyPolicy::Parallel, "b"),
+                ],
+                None
+            ),
+            "a: start\na: end\nb: start\nb: end\n"
+        );
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let output = run(
+            "shutdown",
+            Some(1),
+            vec![
+                (ConcurrencyPolicy::Parallel, "a"),
+                (ConcurrencyPolicy::Parallel, "b"),
+            ],
+            Some(Duration::from_secs(0)),
+        );
+        assert!(!output.contains("end"));
+        assert!(!output.contains("b"));
+
+        assert_eq!(
+            run(
+                "shutdown-finished",
+                None,
+                vec![(ConcurrencyPolicy::Parallel, "a")],
+                Some(Duration::from_secs(5)),
+            ),
+            "a: start\na: end\n"
+        );
+    }
+}
@@ -1,69 +1,289 @@
 //! The wake-on-lan-hook server.
-use std::{
-    net::{Ipv4Addr, SocketAddr},
-    process::Command,
-};
+use std::{io, net::SocketAddr, sync::Arc, time::Instant};
 
+use libc;
+use nix::{
+    net::if_::if_nametoindex,
+    unistd::{getgid, getuid},
+};
 use slog;
 use stream_cancel::{StreamExt, Tripwire};
-use tokio::{
-    self, codec,
-    net::{UdpFramed, UdpSocket},
-    prelude::*,
-};
-use tokio_process::CommandExt;
+use tokio::{self, prelude::*, timer::Interval};
 use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
 
+use acl;
+use config::Config;
+use cooldown::{Cooldowns, Suppression};
+use dedup::Deduplicator;
 use error::Error;
-use mac::MacAddress;
+use ether::{EthernetFrame, EthernetListener, ETHERTYPE_WAKE_ON_LAN};
+use event::{DestinationType, Packet, Remote, WakeEvent};
+use mac::ParsedMagicPacket;
+use privileges::Identity;
+use ratelimit::RateLimiter;
+use scheduler::Scheduler;
+use systemd::ListenFd;
+use udp;
 
-/// The ports to listen on.
+/// A stream of packets received by a listener.
+type PacketStream = Box<dyn Stream<Item = Packet, Error = io::Error> + Send>;
+
+/// Run the wake-on-lan-hook server.
 ///
-/// Wake-on-LAN "magic packets" are sent on UDP ports 0, 7, or 9 *or* as an
-/// Ethernet packet with EtherType `0x0842`.mac
+/// This will start listening on the configured UDP addresses and ports (by
+/// default, ports 0, 7, and 9 over both IPv4 and IPv6, including the IPv6
+/// all-nodes multicast group), or on the UDP sockets passed by systemd if the
+/// daemon was socket activated, and, unless disabled by the configuration, for
+/// raw Ethernet frames with EtherType `0x0842` (which are received by an
+/// [`EthernetListener`]) for wake-on-LAN "magic packets" and run the command of
+/// every configured [`Hook`] whose MAC address matches the packet.
 ///
-/// See [the Wikipedia article][wiki] for more information.
+/// Identical copies of a wake-on-LAN packet that arrive shortly after a hook
+/// accepted it (see [`Deduplicator`]) do not trigger that hook again. Wake-on-LAN packets for other MAC addresses
+/// will be ignored but logged. If a hook requires a SecureOn password, packets
+/// that do not carry a matching password will not trigger that hook. Likewise,
+/// if a hook is restricted to certain network interfaces or kinds of
+/// destination address, packets that arrive on other interfaces or were sent to
+/// other addresses will not trigger it.
 ///
-/// [wiki]: https://en.wikipedia.org/wiki/Wake-on-LAN#Magic_packet
-const WAKE_ON_LAN_PORTS: [u16; 3] = [0, 7, 9];
-
-/// Run the wake-on-lan-hook server.
+/// Packets from sources that are not allowed by the configured source networks
+/// are ignored before they are parsed, and those not allowed by a hook's source
+/// networks do not trigger that hook. Either way, the rule that rejected the
+/// packet is logged.
+///
+/// Packets over the configured rate limits are dropped before anything else is
+/// done with them, and a summary of the dropped packets is logged periodically.
+///
+/// If some of the UDP addresses and ports cannot be bound, the configured
+/// [`BindPolicy`][::config::BindPolicy] decides whether the server gives up or
+/// carries on without them.
 ///
-/// This will start listening on UDP ports 0, 7, and 9 for wake-on-LAN "magic
-/// packets" and run the given command whenever a packet for the desired MAC
-/// address is detected.
+/// If a user or group is configured, the server switches to it once every
+/// listener has been bound, so commands do not run as root.
 ///
-/// Wake-on-LAN packets for other MAC addresses will be ignored but logged.
+/// Upon `SIGINT` or `SIGTERM`, the server stops listening and gives running
+/// commands the configured shutdown grace period to finish before terminating
+/// them.
 ///
 /// See the [`magic_packet()`][::mac::magic_packet] parser for details about what
-/// constitutes a magic packet.
-pub fn run(
-    log: slog::Logger,
-    desired_mac_address: MacAddress,
-    cmd: Vec<String>,
-) -> Result<(), Error> {
-    let ip_addr = Ipv4Addr::new(0, 0, 0, 0).into();
-
-    let listeners = WAKE_ON_LAN_PORTS
-        .iter()
-        .map(|&port| {
-            let socket_addr = SocketAddr::new(ip_addr, port);
-
-            UdpSocket::bind(&socket_addr)
-                .map_err(|e| Error::BindError(port, e))
-                .map(|socket| {
-                    let stream = UdpFramed::new(socket, codec::BytesCodec::new());
-                    let log = log.new(o!{"port" => port});
-
-                    (log, stream)
-                })
-        }).collect::<Result<Vec<_>, Error>>()?;
+/// constitutes a magic packet. If the configuration enables scanning, a magic
+/// packet may also appear anywhere inside a larger payload (see
+/// [`ParsedMagicPacket::scan`][::mac::ParsedMagicPacket::scan]), and its
+/// offset and the number of bytes that follow it are logged.
+pub fn run(log: slog::Logger, config: Config, listen_fds: Vec<ListenFd>) -> Result<(), Error> {
+    let hooks = Arc::new(config.hooks);
+    let cooldowns = Cooldowns::default();
+    let deduplicator = Deduplicator::new(config.deduplication_window);
+    let allow_sources = Arc::new(config.allow_sources);
+    let deny_sources = Arc::new(config.deny_sources);
+    let rate_limiter = RateLimiter::new(config.rate_limit, config.source_rate_limit);
+    let scan_payloads = config.scan_payloads;
+    let scheduler = Scheduler::new(config.max_concurrent_commands);
+
+    // Look up the identity to switch to before binding, so that a typo does not
+    // leave us listening as root.
+    let identity = Identity::resolve(
+        config.user.as_ref().map(AsRef::as_ref),
+        config.group.as_ref().map(AsRef::as_ref),
+    )?;
+
+    // If no interfaces are configured, listen on every interface.
+    let interfaces = if config.interfaces.is_empty() {
+        vec![None]
+    } else {
+        config
+            .interfaces
+            .iter()
+            .map(|name| {
+                if_nametoindex(name.as_str())
+                    .map(|index| Some((name.clone(), index)))
+                    .map_err(|_| Error::UnknownInterfaceError(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    // Bind the UDP sockets ourselves unless systemd has passed them to us.
+    let mut udp_listeners = Vec::new();
+    let mut bind_failures = Vec::new();
+    if listen_fds.is_empty() {
+        for interface in &interfaces {
+            let interface_name = interface.as_ref().map(|(name, _)| name.clone());
+            let log = match interface_name {
+                Some(ref name) => log.new(o!{"interface" => name.clone()}),
+                None => log.clone(),
+            };
+
+            for ip_addr in &config.addresses {
+                for &port in &config.ports {
+                    let socket_addr = SocketAddr::new(*ip_addr, port);
+
+                    match udp::bind(&socket_addr, interface_name.as_ref().map(AsRef::as_ref)) {
+                        Ok(listener) => {
+                            udp_listeners.push((log.clone(), interface_name.clone(), listener))
+                        }
+                        Err(ref e)
+                            if ip_addr.is_unspecified()
+                                && ip_addr.is_ipv6()
+                                && e.raw_os_error() == Some(libc::EAFNOSUPPORT) =>
+                        {
+                            warn!(log, "IPv6 is not supported; not listening on {}", ip_addr);
+                            break;
+                        }
+                        Err(e) => {
+                            if !config.bind_policy.tolerates(port) {
+                                return Err(Error::BindError(socket_addr, e));
+                            }
+
+                            warn!(
+                                log,
+                                "Could not bind to wake-on-LAN address; continuing without it";
+                                "address" => %socket_addr,
+                                "error" => %e,
+                            );
+                            bind_failures.push((socket_addr, e));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Even the most lenient policy needs something to listen on.
+        if udp_listeners.is_empty() && !bind_failures.is_empty() {
+            let (socket_addr, e) = bind_failures.remove(0);
+            return Err(Error::BindError(socket_addr, e));
+        }
+    } else {
+        if !config.interfaces.is_empty() {
+            return Err(Error::ConfigError(
+                "`interfaces` cannot be used with socket activation; use `BindToDevice=` in the socket unit instead".into(),
+            ));
+        }
+
+        info!(
+            log,
+            "Using sockets passed by systemd; ignoring the configured addresses and ports"
+        );
+
+        for listen_fd in listen_fds {
+            let description = match listen_fd.name {
+                Some(ref name) => format!("{} ({})", listen_fd.fd, name),
+                None => listen_fd.fd.to_string(),
+            };
+
+            let listener = udp::from_raw_fd(listen_fd.fd)
+                .map_err(|e| Error::ActivatedSocketError(description, e))?;
+            udp_listeners.push((log.clone(), None, listener));
+        }
+    }
+
+    let mut listeners = Vec::new();
+    let mut bound = Vec::new();
+    for (log, interface_name, listener) in udp_listeners {
+        // If the port was 0, the socket was bound to an ephemeral port.
+        let local_addr = listener.local_addr().map_err(Error::Io)?;
+        let local_port = local_addr.port();
+        let log = log.new(o!{"address" => local_addr.to_string()});
+
+        if local_addr.ip().is_unspecified() && local_addr.is_ipv6() {
+            match udp::join_all_nodes(&listener, interface_name.as_ref().map(AsRef::as_ref)) {
+                Ok(interfaces) => info!(
+                    log,
+                    "Joined the IPv6 all-nodes multicast group";
+                    "interfaces" => interfaces.join(", "),
+                ),
+                Err(e) => warn!(
+                    log,
+                    "Could not join the IPv6 all-nodes multicast group";
+                    "error" => %e,
+                ),
+            }
+        }
+
+        let stream: PacketStream = Box::new(listener.map({
+            let interface_name = interface_name.clone();
+            move |datagram| Packet {
+                payload: datagram.payload,
+                remote: Remote::Udp(datagram.source),
+                local_port: Some(local_port),
+                interface: interface_name.clone(),
+                interface_index: datagram.interface_index,
+                destination: datagram.destination,
+                destination_type: None,
+            }
+        }));
+
+        bound.push(match interface_name {
+            Some(ref name) => format!("{}%{}", local_addr, name),
+            None => local_addr.to_string(),
+        });
+        listeners.push((log, stream));
+    }
 
     info!(
         log,
-        "Listening for wake-on-LAN packets on ports 0, 7, and 9"
+        "Listening for wake-on-LAN packets";
+        "addresses" => bound.join(", "),
     );
 
+    if config.ethernet {
+        for interface in &interfaces {
+            let (interface_name, interface_index) = match *interface {
+                Some((ref name, index)) => (Some(name.clone()), index),
+                None => (None, 0),
+            };
+
+            let log = log.new(o!{"ethertype" => format!("0x{:04X}", ETHERTYPE_WAKE_ON_LAN)});
+            let log = match interface_name {
+                Some(ref name) => log.new(o!{"interface" => name.clone()}),
+                None => log,
+            };
+
+            let stream: PacketStream = Box::new(
+                EthernetListener::bind(interface_index)
+                    .map_err(Error::EthernetBindError)?
+                    .filter_map({
+                        let log = log.clone();
+                        move |(frame, index)| match EthernetFrame::parse(&frame) {
+                            Ok(ref frame) if !frame.is_wake_on_lan() => {
+                                debug!(
+                                    log,
+                                    "Ignoring Ethernet frame with another EtherType";
+                                    "frame_ethertype" => format!("0x{:04X}", frame.ethertype),
+                                );
+                                None
+                            }
+                            Ok(frame) => Some(Packet {
+                                payload: frame.payload.to_vec(),
+                                remote: Remote::Ethernet(frame.source),
+                                local_port: None,
+                                interface: interface_name.clone(),
+                                interface_index: Some(index),
+                                destination: None,
+                                destination_type: Some(DestinationType::of_mac(frame.destination)),
+                            }),
+                            Err(e) => {
+                                info!(log, "Received invalid Ethernet frame"; "error" => %e);
+                                None
+                            }
+                        }
+                    }),
+            );
+
+            info!(log, "Listening for raw Ethernet wake-on-LAN frames");
+            listeners.push((log, stream));
+        }
+    }
+
+    if let Some(identity) = identity {
+        identity.switch()?;
+        info!(
+            log,
+            "Dropped privileges";
+            "uid" => %getuid(),
+            "gid" => %getgid(),
+        );
+    }
+
     let mut runtime = tokio::runtime::Runtime::new().expect("Could not create tokio runtime");
 
     let (signal_handler, tripwire) = signal_guard(log.clone());
@@ -76,7 +296,41 @@ pub fn run(
     });
     runtime.spawn(signal_handler);
 
+    if config.rate_limit.is_some() || config.source_rate_limit.is_some() {
+        let interval = config.rate_limit_summary_interval;
+        let summarizer = Interval::new(Instant::now() + interval, interval)
+            .map_err({
+                let log = log.clone();
+                move |e| {
+                    error!(log, "Error in rate limit summary timer"; "error" => %e);
+                    ()
+                }
+            })
+            .take_until(tripwire.clone())
+            .for_each({
+                let log = log.clone();
+                let rate_limiter = rate_limiter.clone();
+                move |_| {
+                    if let Some(summary) = rate_limiter.summary(Instant::now()) {
+                        warn!(
+                            log,
+                            "Dropped wake-on-LAN packets over the rate limit";
+                            "interval" => ?interval,
+                            "source_limited" => summary.source_limited,
+                            "global_limited" => summary.global_limited,
+                            "sources" => summary.sources,
+                            "busiest_source" => summary.busiest_source.map(|(source, _)| source.to_string()),
+                            "busiest_source_dropped" => summary.busiest_source.map(|(_, dropped)| dropped),
+                        );
+                    }
+                    Ok(())
+                }
+            });
+        runtime.spawn(summarizer);
+    }
+
     let servers = listeners.into_iter().map({
+        let scheduler = scheduler.clone();
         move |(log, stream)| {
             stream
                 .map_err({
@@ -88,69 +342,173 @@ pub fn run(
                 })
                 .take_until(tripwire.clone())
                 .for_each({
-                    let cmd = cmd.clone();
-                    move |(bytes, addr)| {
-                        let log = log.new(o!{"remote" => addr});
+                    let hooks = hooks.clone();
+                    let cooldowns = cooldowns.clone();
+                    let deduplicator = deduplicator.clone();
+                    let allow_sources = allow_sources.clone();
+                    let deny_sources = deny_sources.clone();
+                    let rate_limiter = rate_limiter.clone();
+                    let scheduler = scheduler.clone();
+                    move |mut packet: Packet| {
+                        // Dropped packets are counted rather than logged, so
+                        // that a flood cannot fill the log.
+                        if rate_limiter.check(&packet.remote, Instant::now()).is_err() {
+                            return Ok(());
+                        }
 
-                        let _mac_address = match MacAddress::from_magic_packet(&bytes) {
+                        if let Err(rejection) =
+                            acl::check(&allow_sources, &deny_sources, &packet.remote)
+                        {
+                            info!(
+                                packet_logger(&log, &packet),
+                                "Ignoring wake-on-LAN packet from a rejected source";
+                                "rule" => %rejection,
+                            );
+                            return Ok(());
+                        }
+
+                        // Only packets that got this far are worth the system
+                        // calls it takes to classify them.
+                        packet.classify();
+                        let log = packet_logger(&log, &packet);
+
+                        let result = if scan_payloads {
+                            ParsedMagicPacket::scan(&packet.payload)
+                        } else {
+                            ParsedMagicPacket::parse(&packet.payload)
+                        };
+
+                        let magic_packet = match result {
                             Err(e) => {
                                 info!(log, "Received invalid wake-on-LAN packet"; "error" => %e);
-                                return future::Either::A(future::ok(()));
+                                return Ok(());
                             }
 
-                            Ok(mac_address) if mac_address != desired_mac_address => {
+                            Ok(result) => result,
+                        };
+
+                        let mac_address = magic_packet.target;
+                        let mut log = log.new(o!{"mac_address" => mac_address.to_string()});
+                        if magic_packet.offset > 0 {
+                            log = log.new(o!{"offset" => magic_packet.offset});
+                        }
+                        if !magic_packet.trailer.is_empty() {
+                            log = log.new(o!{"trailing_bytes" => magic_packet.trailer.len()});
+                        }
+
+                        let matching_hooks = hooks
+                            .iter()
+                            .filter(|hook| hook.mac_address == mac_address)
+                            .collect::<Vec<_>>();
+
+                        if matching_hooks.is_empty() {
+                            info!(log, "Received wake-on-LAN packet for unknown MAC address");
+                            return Ok(());
+                        }
+
+                        info!(log, "Received wake-on-LAN packet");
+                        let event = WakeEvent::new(&packet, magic_packet);
+
+                        for hook in matching_hooks {
+                            let log = log.new(o!{"hook" => hook.name.clone()});
+
+                            if !hook.interfaces.is_empty()
+                                && !packet
+                                    .interface
+                                    .as_ref()
+                                    .map(|interface| hook.interfaces.contains(interface))
+                                    .unwrap_or(false)
+                            {
                                 info!(
                                     log,
-                                    "Recieved wake-on-LAN packet for different mac address";
-                                    "desired_mac_address" => %desired_mac_address,
-                                    "received_mac_address" => %mac_address,
+                                    "Ignoring wake-on-LAN packet received on another interface"
                                 );
-                                return future::Either::A(future::ok(()));
+                                continue;
                             }
 
-                            Ok(mac_address) => {
-                                info!(log, "Received wake-on-LAN packet"; "mac_address" => %mac_address);
-                                mac_address
+                            if let Err(rejection) =
+                                acl::check(&hook.allow_sources, &hook.deny_sources, &packet.remote)
+                            {
+                                info!(
+                                    log,
+                                    "Ignoring wake-on-LAN packet from a rejected source";
+                                    "rule" => %rejection,
+                                );
+                                continue;
                             }
-                        };
 
-                        assert!(cmd.len() > 1);
-                        let log = log.new(o!{"command" => format!("{:?}", cmd)});
-                        let command_future = Command::new(&cmd[0])
-                            .args(&cmd[1..])
-                            .output_async()
-                            .map_err({
-                                let log = log.clone();
-                                move |e| {
-                                    crit!(log, "failed to communicate with process"; "error" => %e);
-                                    ()
+                            if !hook.destination_types.is_empty()
+                                && !packet
+                                    .destination_type
+                                    .map(|destination_type| {
+                                        hook.destination_types.contains(&destination_type)
+                                    })
+                                    .unwrap_or(false)
+                            {
+                                info!(
+                                    log,
+                                    "Ignoring wake-on-LAN packet sent to another kind of address"
+                                );
+                                continue;
+                            }
+
+                            if hook.password.is_some()
+                                && event.magic_packet.password != hook.password
+                            {
+                                info!(
+                                    log,
+                                    "Received wake-on-LAN packet with incorrect SecureOn password";
+                                    "has_password" => event.magic_packet.password.is_some(),
+                                );
+                                continue;
+                            }
+
+                            // Copies of a packet we have just seen (e.g., sent to
+                            // another port) are part of the same wake request.
+                            if let Some(elapsed) =
+                                deduplicator.check(hook, mac_address, &packet, Instant::now())
+                            {
+                                debug!(
+                                    log,
+                                    "Ignoring duplicate wake-on-LAN packet";
+                                    "first_received" => ?elapsed,
+                                );
+                                continue;
+                            }
+
+                            let guard = match cooldowns.trigger(hook, mac_address, Instant::now()) {
+                                Ok(guard) => guard,
+                                Err(Suppression::Running) => {
+                                    info!(
+                                        log,
+                                        "Ignoring wake-on-LAN packet; the command is still running"
+                                    );
+                                    continue;
                                 }
-                            })
-                            .map({
-                                let log = log.clone();
-                                move |output| {
-                                    let log = log.new(o!{
-                                        "stdout" => utf8_or_raw(&output.stdout),
-                                        "stderr" => utf8_or_raw(&output.stderr),
-                                    });
-
-                                    if output.status.success() {
-                                        info!(log, "Command executed successfully");
-                                        future::ok(())
-                                    } else {
-                                        error!(log, "Command executed unsuccessfully"; "status" => output.status.code());
-                                        future::err(())
-                                    }
+                                Err(Suppression::CoolingDown { remaining }) => {
+                                    info!(
+                                        log,
+                                        "Ignoring wake-on-LAN packet; the hook is cooling down";
+                                        "remaining" => ?remaining,
+                                    );
+                                    continue;
                                 }
-                            });
+                            };
+
+                            scheduler.trigger(log, hook, event.clone(), guard);
+                        }
 
-                        future::Either::B(command_future.map(|_| ()))
+                        Ok(())
                     }
                 })
         }
     });
 
-    let server = future::join_all(servers).map(|_| ());
+    let shutdown_grace_period = config.shutdown_grace_period;
+    let server = future::join_all(servers).and_then(move |_| {
+        info!(log, "Stopped listening for wake-on-LAN packets");
+        scheduler.shutdown(log, shutdown_grace_period)
+    });
 
     runtime.spawn(server);
     runtime
@@ -161,14 +519,25 @@ pub fn run(
     Ok(())
 }
 
-/// Attempt to parse the bytes as UTF-8.
+/// Create a logger for a received packet.
 ///
-/// If the bytes cannot be parsed as UTF-8 successfully, the `Debug`
-/// representation of the bytes will be used instead.
-fn utf8_or_raw(bytes: &[u8]) -> String {
-    ::std::str::from_utf8(bytes)
-        .map(Into::into)
-        .unwrap_or_else(|_| format!("{:?}", bytes))
+/// The interface and destination are only logged when they are known.
+fn packet_logger(log: &slog::Logger, packet: &Packet) -> slog::Logger {
+    let mut log = log.new(o!{"remote" => packet.remote});
+
+    if let Some(ref interface) = packet.interface {
+        log = log.new(o!{"received_on" => interface.clone()});
+    }
+
+    if let Some(destination) = packet.destination {
+        log = log.new(o!{"destination" => destination.to_string()});
+    }
+
+    if let Some(destination_type) = packet.destination_type {
+        log = log.new(o!{"destination_type" => destination_type.as_str()});
+    }
+
+    log
 }
 
 /// Generate a signal handling future for SIGINT and SIGTERM and a tripwire that
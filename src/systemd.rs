@@ -0,0 +1,155 @@
+//! Support for systemd socket activation.
+//!
+//! When started by a `.socket` unit, systemd binds the sockets itself and passes
+//! them to the daemon as file descriptors starting at 3. They are described by
+//! the `LISTEN_PID`, `LISTEN_FDS`, and `LISTEN_FDNAMES` environment variables
+//! (see `sd_listen_fds(3)`).
+
+use std::{env, os::unix::io::RawFd};
+
+use libc;
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag},
+    unistd::getpid,
+};
+
+use error::Error;
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// A socket passed to the daemon by systemd.
+pub struct ListenFd {
+    /// The file descriptor of the socket.
+    pub fd: RawFd,
+
+    /// The name of the socket, as set by `FileDescriptorName=` in the socket
+    /// unit.
+    pub name: Option<String>,
+}
+
+/// Take the sockets passed to the daemon by systemd.
+///
+/// The socket activation variables are removed from the environment and the
+/// sockets are marked close-on-exec so that neither are inherited by hook
+/// commands. If the daemon was not socket activated, no sockets are returned.
+pub fn listen_fds() -> Result<Vec<ListenFd>, Error> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").ok();
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let listen_fds = match (pid, fds) {
+        (Some(pid), Some(fds)) => parse(
+            &pid,
+            &fds,
+            names.as_ref().map(AsRef::as_ref),
+            libc::pid_t::from(getpid()),
+        )?,
+        _ => return Ok(Vec::new()),
+    };
+
+    for listen_fd in &listen_fds {
+        fcntl(listen_fd.fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(|e| {
+            Error::SocketActivationError(format!(
+                "could not use file descriptor {}: {}",
+                listen_fd.fd, e
+            ))
+        })?;
+    }
+
+    Ok(listen_fds)
+}
+
+/// Parse the socket activation variables.
+///
+/// If `LISTEN_PID` is not our PID, the sockets were meant for another process
+/// and none are returned.
+fn parse(
+    pid: &str,
+    fds: &str,
+    names: Option<&str>,
+    own_pid: libc::pid_t,
+) -> Result<Vec<ListenFd>, Error> {
+    let pid = pid
+        .parse::<libc::pid_t>()
+        .map_err(|_| Error::SocketActivationError(format!("invalid LISTEN_PID `{}`", pid)))?;
+
+    if pid != own_pid {
+        return Ok(Vec::new());
+    }
+
+    let count = fds
+        .parse::<usize>()
+        .map_err(|_| Error::SocketActivationError(format!("invalid LISTEN_FDS `{}`", fds)))?;
+
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let names = match names {
+        Some(names) => {
+            let names = names.split(':').map(String::from).collect::<Vec<_>>();
+            if names.len() != count {
+                return Err(Error::SocketActivationError(format!(
+                    "LISTEN_FDNAMES has {} names but LISTEN_FDS is {}",
+                    names.len(),
+                    count
+                )));
+            }
+            names.into_iter().map(Some).collect()
+        }
+        None => vec![None; count],
+    };
+
+    Ok(names
+        .into_iter()
+        .zip(LISTEN_FDS_START..)
+        .map(|(name, fd)| ListenFd { fd, name })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("100", "2", Some("wake-on-lan-hook.socket:ipv6"), 100).unwrap(),
+            vec![
+                ListenFd {
+                    fd: 3,
+                    name: Some("wake-on-lan-hook.socket".into()),
+                },
+                ListenFd {
+                    fd: 4,
+                    name: Some("ipv6".into()),
+                },
+            ]
+        );
+        assert_eq!(
+            parse("100", "1", None, 100).unwrap(),
+            vec![ListenFd { fd: 3, name: None }]
+        );
+        assert_eq!(parse("100", "0", Some(""), 100).unwrap(), vec![]);
+        assert_eq!(parse("101", "1", None, 100).unwrap(), vec![]);
+
+        assert_matches!(
+            parse("self", "1", None, 100),
+            Err(Error::SocketActivationError(..))
+        );
+        assert_matches!(
+            parse("100", "-1", None, 100),
+            Err(Error::SocketActivationError(..))
+        );
+        assert_matches!(
+            parse("100", "2", Some("a"), 100),
+            Err(Error::SocketActivationError(..))
+        );
+    }
+}
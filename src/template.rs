@@ -0,0 +1,353 @@
+//! Templates for command arguments.
+//!
+//! Each argument of a hook's command is a template that is expanded when the
+//! hook runs. Templates may contain the following placeholders:
+//!
+//! * `{hook}`: the name of the hook;
+//! * `{mac}`: the MAC address the packet was for;
+//! * `{transport}`: either `udp` or `ethernet`;
+//! * `{source_ip}`: the IP address the packet was sent from;
+//! * `{source_port}`: the UDP port the packet was sent from;
+//! * `{source_mac}`: the hardware address a raw Ethernet frame was sent from;
+//! * `{port}`: the local UDP port the packet was received on;
+//! * `{interface}`: the network interface the packet was received on;
+//! * `{destination_ip}`: the IP address the packet was sent to;
+//! * `{destination_type}`: the kind of address the packet was sent to
+//!   (`unicast`, `broadcast`, `subnet-broadcast`, or `multicast`);
+//! * `{password}`: the SecureOn password included in the packet;
+//! * `{offset}`: the offset of the magic packet in the payload, which is only
+//!   nonzero if payloads are scanned;
+//! * `{trailer}`: any bytes that followed the magic packet, other than the
+//!   SecureOn password, in hexadecimal;
+//! * `{payload}`: the whole payload, in hexadecimal; and
+//! * `{timestamp}`: when the packet was received, in seconds since the Unix
+//!   epoch.
+//!
+//! The MAC address placeholders, `{mac}` and `{source_mac}`, may be followed by
+//! a [`MacAddressFormat`] to write the address in another notation or case,
+//! e.g., `{mac:dash,lower}` for `aa-bb-cc-dd-ee-ff` or `{mac:dotted}` for
+//! `AABB.CCDD.EEFF`.
+//!
+//! Placeholders that do not apply to a packet (e.g., `{source_ip}` for a raw
+//! Ethernet frame) expand to the empty string. Literal braces are written as
+//! `{{` and `}}`, so braces in shell scripts or `jq` filters must be doubled
+//! (e.g., `sh -c 'echo ${{HOME}}'`).
+//!
+//! Each argument is expanded on its own, so an expanded value never splits into
+//! or merges with other arguments.
+
+use std::str::FromStr;
+
+use combine::{
+    combinator::{between, choice, eof, many, many1, optional, satisfy, token, try},
+    parser::char::string,
+    stream::state::{IndexPositioner, State},
+    ParseError, Parser, Stream,
+};
+
+use error::Error;
+use event::{Remote, WakeEvent};
+use mac::{self, MacAddressFormat};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// A value that can be substituted into a template.
+enum Placeholder {
+    Hook,
+    Mac(MacAddressFormat),
+    Transport,
+    SourceIp,
+    SourcePort,
+    SourceMac(MacAddressFormat),
+    Port,
+    Interface,
+    DestinationIp,
+    DestinationType,
+    Password,
+    Offset,
+    Trailer,
+    Payload,
+    Timestamp,
+}
+
+impl Placeholder {
+    /// Look up a placeholder by its name and, for MAC address placeholders,
+    /// its format.
+    fn from_name(name: &str, format: Option<&str>) -> Result<Self, Error> {
+        let mac_address_format = || match format {
+            Some(format) => format.parse(),
+            None => Ok(MacAddressFormat::default()),
+        };
+
+        let placeholder = match name {
+            "mac" => return mac_address_format().map(Placeholder::Mac),
+            "source_mac" => return mac_address_format().map(Placeholder::SourceMac),
+            "hook" => Placeholder::Hook,
+            "transport" => Placeholder::Transport,
+            "source_ip" => Placeholder::SourceIp,
+            "source_port" => Placeholder::SourcePort,
+            "port" => Placeholder::Port,
+            "interface" => Placeholder::Interface,
+            "destination_ip" => Placeholder::DestinationIp,
+            "destination_type" => Placeholder::DestinationType,
+            "password" => Placeholder::Password,
+            "offset" => Placeholder::Offset,
+            "trailer" => Placeholder::Trailer,
+            "payload" => Placeholder::Payload,
+            "timestamp" => Placeholder::Timestamp,
+            _ => return Err(Error::UnknownPlaceholderError(name.into())),
+        };
+
+        // Only MAC address placeholders have formats.
+        match format {
+            Some(format) => Err(Error::UnknownPlaceholderError(format!(
+                "{}:{}",
+                name, format
+            ))),
+            None => Ok(placeholder),
+        }
+    }
+
+    /// The value of the placeholder for the given hook and event.
+    fn value(self, hook_name: &str, event: &WakeEvent) -> String {
+        match (self, event.remote) {
+            (Placeholder::Hook, _) => hook_name.into(),
+            (Placeholder::Mac(format), _) => event.magic_packet.target.display(format).to_string(),
+            (Placeholder::Transport, Remote::Udp(..)) => "udp".into(),
+            (Placeholder::Transport, Remote::Ethernet(..)) => "ethernet".into(),
+            (Placeholder::SourceIp, Remote::Udp(addr)) => addr.ip().to_string(),
+            (Placeholder::SourcePort, Remote::Udp(addr)) => addr.port().to_string(),
+            (Placeholder::SourceMac(format), Remote::Ethernet(mac)) => {
+                mac.display(format).to_string()
+            }
+            (Placeholder::Port, _) => event
+                .local_port
+                .map(|port| port.to_string())
+                .unwrap_or_default(),
+            (Placeholder::Interface, _) => event.interface.clone().unwrap_or_default(),
+            (Placeholder::DestinationIp, _) => event
+                .destination
+                .map(|addr| addr.to_string())
+                .unwrap_or_default(),
+            (Placeholder::DestinationType, _) => event
+                .destination_type
+                .map(|destination_type| destination_type.to_string())
+                .unwrap_or_default(),
+            (Placeholder::Password, _) => event
+                .magic_packet
+                .password
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            (Placeholder::Offset, _) => event.magic_packet.offset.to_string(),
+            (Placeholder::Trailer, _) => mac::to_hex(&event.magic_packet.trailer),
+            (Placeholder::Payload, _) => mac::to_hex(&event.magic_packet.payload),
+            (Placeholder::Timestamp, _) => event.unix_timestamp().to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// A part of a template.
+enum Segment {
+    /// Text that is copied verbatim.
+    Literal(String),
+
+    /// A value that is substituted when the template is expanded.
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// A command argument that may contain placeholders.
+pub struct Template(Vec<Segment>);
+
+impl Template {
+    /// Expand the template for the given hook and event.
+    pub fn expand(&self, hook_name: &str, event: &WakeEvent) -> String {
+        self.0
+            .iter()
+            .map(|segment| match *segment {
+                Segment::Literal(ref s) => s.clone(),
+                Segment::Placeholder(placeholder) => placeholder.value(hook_name, event),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stream = State::with_positioner(s, IndexPositioner::new());
+        let (raw_segments, _) = template()
+            .easy_parse(stream)
+            .map_err(|e| Error::TemplateParseError(s.into(), e.map_range(String::from)))?;
+
+        raw_segments
+            .into_iter()
+            .map(|segment| match segment {
+                RawSegment::Literal(s) => Ok(Segment::Literal(s)),
+                RawSegment::Placeholder(name, format) => {
+                    Placeholder::from_name(&name, format.as_ref().map(AsRef::as_ref))
+                        .map(Segment::Placeholder)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Template)
+    }
+}
+
+/// A part of a template before its placeholders have been resolved.
+enum RawSegment {
+    Literal(String),
+    Placeholder(String, Option<String>),
+}
+
+/// Return a parser for a template.
+///
+/// A template is a sequence of literal text, escaped braces (`{{` or `}}`), and
+/// placeholders (a name, optionally followed by a colon and a format, between
+/// braces, e.g., `{mac}` or `{mac:dash}`).
+fn template<I>() -> impl Parser<Input = I, Output = Vec<RawSegment>>
+where
+    I: Stream<Item = char>,
+    I::Error: ParseError<I::Item, I::Range, I::Position>,
+{
+    let escape = choice((
+        try(string("{{")).map(|_| "{"),
+        try(string("}}")).map(|_| "}"),
+    ))
+    .map(|s: &str| RawSegment::Literal(s.into()));
+
+    let placeholder = between(
+        token('{'),
+        token('}'),
+        (
+            many1(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '_')),
+            optional(token(':').with(many1(satisfy(|c| c != '{' && c != '}')))),
+        ),
+    )
+    .map(|(name, format)| RawSegment::Placeholder(name, format));
+
+    let literal = many1(satisfy(|c| c != '{' && c != '}')).map(RawSegment::Literal);
+
+    many(choice((escape, placeholder, literal))).skip(eof())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use event::{test_event, DestinationType};
+    use mac::{MacAddress, MagicPacket, ParsedMagicPacket};
+
+    fn expand(template: &str, event: &WakeEvent) -> String {
+        Template::from_str(template).unwrap().expand("vm", event)
+    }
+
+    #[test]
+    fn test_expand() {
+        let event = test_event();
+
+        assert_eq!(expand("start", &event), "start");
+        assert_eq!(expand("", &event), "");
+        assert_eq!(expand("{hook}.service", &event), "vm.service");
+        assert_eq!(
+            expand("{mac} from {source_ip}:{source_port} on {port}", &event),
+            "AA:BB:CC:DD:EE:FF from 192.168.1.2:4000 on 9"
+        );
+        assert_eq!(expand("{transport}", &event), "udp");
+        assert_eq!(expand("{mac:lower}", &event), "aa:bb:cc:dd:ee:ff");
+        assert_eq!(expand("{mac:dash, lower}", &event), "aa-bb-cc-dd-ee-ff");
+        assert_eq!(expand("{mac:dotted}", &event), "AABB.CCDD.EEFF");
+        assert_eq!(
+            expand(
+                "{destination_ip} ({destination_type}) on {interface}",
+                &event
+            ),
+            "192.168.1.255 (subnet-broadcast) on eth0"
+        );
+        assert_eq!(expand("{timestamp}", &event), "1500000000");
+        assert_eq!(expand("[{password}]", &event), "[]");
+        assert_eq!(expand("{offset} [{trailer}]", &event), "0 []");
+        assert_eq!(
+            expand("{payload}", &event),
+            format!("ffffffffffff{}", "aabbccddeeff".repeat(16))
+        );
+        assert_eq!(expand("[{source_mac}]", &event), "[]");
+        assert_eq!(expand("{{hook}}", &event), "{hook}");
+        assert_eq!(expand("${{HOME}}", &event), "${HOME}");
+        assert_eq!(expand("{{{hook}}}", &event), "{vm}");
+
+        let mut payload = b"WOL".to_vec();
+        payload.extend(
+            MagicPacket::new(MacAddress(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff))
+                .with_password("1.2.3.4".parse().unwrap())
+                .to_bytes(),
+        );
+        let event = WakeEvent {
+            magic_packet: ParsedMagicPacket::scan(&payload).unwrap(),
+            remote: Remote::Ethernet(MacAddress(1, 2, 3, 4, 5, 6)),
+            local_port: None,
+            destination: None,
+            destination_type: Some(DestinationType::Broadcast),
+            ..test_event()
+        };
+
+        assert_eq!(expand("{transport}", &event), "ethernet");
+        assert_eq!(expand("{source_mac}", &event), "01:02:03:04:05:06");
+        assert_eq!(expand("{source_mac:bare,lower}", &event), "010203040506");
+        assert_eq!(
+            expand("[{source_ip}][{port}][{destination_ip}]", &event),
+            "[][][]"
+        );
+        assert_eq!(expand("{destination_type}", &event), "broadcast");
+        assert_eq!(expand("{password}", &event), "1.2.3.4");
+        assert_eq!(expand("{offset}", &event), "3");
+        assert_eq!(expand("{payload}", &event)[..6], *"574f4c");
+
+        assert_eq!(
+            expand("a value with spaces; {hook}", &event),
+            "a value with spaces; vm"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_matches!(
+            Template::from_str("{unknown}"),
+            Err(Error::UnknownPlaceholderError(ref name)) if name == "unknown"
+        );
+        assert_matches!(
+            Template::from_str("${HOME}"),
+            Err(Error::UnknownPlaceholderError(ref name)) if name == "HOME"
+        );
+        assert_matches!(
+            Template::from_str("{port:dash}"),
+            Err(Error::UnknownPlaceholderError(ref name)) if name == "port:dash"
+        );
+        assert_matches!(
+            Template::from_str("{mac:dash,dotted}"),
+            Err(Error::MacAddressFormatParseError(ref format)) if format == "dash,dotted"
+        );
+        assert_matches!(
+            Template::from_str("{mac:}"),
+            Err(Error::TemplateParseError(..))
+        );
+        assert_matches!(
+            Template::from_str("{mac"),
+            Err(Error::TemplateParseError(..))
+        );
+        assert_matches!(Template::from_str("{}"), Err(Error::TemplateParseError(..)));
+        assert_matches!(
+            Template::from_str("mac}"),
+            Err(Error::TemplateParseError(..))
+        );
+        assert_matches!(
+            Template::from_str("{mac address}"),
+            Err(Error::TemplateParseError(..))
+        );
+    }
+}
@@ -0,0 +1,483 @@
+//! UDP sockets for wake-on-LAN packets.
+
+use std::{
+    collections::BTreeSet,
+    io, mem,
+    net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    ptr,
+};
+
+use libc;
+use mio::{self, Ready};
+use nix::{
+    self,
+    ifaddrs::getifaddrs,
+    net::if_::{if_nametoindex, InterfaceFlags},
+    sys::socket::{self, AddressFamily, InetAddr, SockAddr, SockFlag, SockType},
+};
+use tokio::{prelude::*, reactor::PollEvented2};
+
+/// The IPv6 link-local all-nodes multicast group.
+///
+/// Wake-on-LAN senders use this in place of the IPv4 broadcast address.
+pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+/// The largest datagram we can receive.
+const MAX_DATAGRAM_LEN: usize = 65_535;
+
+/// The size of the buffer for control messages, in `u64`s so that it is
+/// suitably aligned for a `cmsghdr`.
+///
+/// We only ask for a single `in_pktinfo` or `in6_pktinfo` message.
+const CONTROL_LEN: usize = 8;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+/// A datagram received by a [`UdpListener`].
+pub struct Datagram {
+    /// The payload of the datagram.
+    pub payload: Vec<u8>,
+
+    /// The address the datagram was sent from.
+    pub source: SocketAddr,
+
+    /// The address the datagram was sent to, from its IP header.
+    pub destination: Option<IpAddr>,
+
+    /// The index of the interface the datagram was received on.
+    pub interface_index: Option<u32>,
+}
+
+/// A stream of UDP datagrams, including the address each one was sent to and
+/// the interface it was received on.
+pub struct UdpListener {
+    io: PollEvented2<mio::net::UdpSocket>,
+    buf: Vec<u8>,
+}
+
+impl UdpListener {
+    /// The local address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+}
+
+impl Stream for UdpListener {
+    type Item = Datagram;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Async::NotReady = self.io.poll_read_ready(Ready::readable())? {
+            return Ok(Async::NotReady);
+        }
+
+        match recv(self.io.get_ref().as_raw_fd(), &mut self.buf) {
+            Ok(datagram) => Ok(Async::Ready(Some(datagram))),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.io.clear_read_ready(Ready::readable())?;
+                Ok(Async::NotReady)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Bind a UDP socket to the given address.
+///
+/// IPv6 sockets only receive IPv6 packets so that they can share ports with
+/// IPv4 sockets. If an interface is given, the socket is bound to it with
+/// `SO_BINDTODEVICE` and will only receive packets that arrive on it.
+pub fn bind(addr: &SocketAddr, interface: Option<&str>) -> io::Result<UdpListener> {
+    let family = match *addr {
+        SocketAddr::V4(..) => AddressFamily::Inet,
+        SocketAddr::V6(..) => AddressFamily::Inet6,
+    };
+
+    let fd = socket::socket(family, SockType::Datagram, SockFlag::SOCK_CLOEXEC, None)
+        .map_err(nix_to_io)?;
+    let std_socket = unsafe { net::UdpSocket::from_raw_fd(fd) };
+
+    if addr.is_ipv6() {
+        set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, 1)?;
+    }
+    enable_packet_info(fd, addr)?;
+
+    if let Some(interface) = interface {
+        bind_to_device(fd, interface)?;
+    }
+
+    socket::bind(fd, &SockAddr::Inet(InetAddr::from_std(addr))).map_err(nix_to_io)?;
+
+    listen(std_socket)
+}
+
+/// Listen on an already-bound UDP socket, such as one passed by systemd.
+///
+/// This takes ownership of the file descriptor. Returns an error if it is not
+/// a bound UDP socket.
+pub fn from_raw_fd(fd: RawFd) -> io::Result<UdpListener> {
+    let std_socket = unsafe { net::UdpSocket::from_raw_fd(fd) };
+
+    if get_option(fd, libc::SOL_SOCKET, libc::SO_TYPE)? != libc::SOCK_DGRAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a datagram socket",
+        ));
+    }
+
+    let addr = std_socket.local_addr()?;
+    enable_packet_info(fd, &addr)?;
+
+    listen(std_socket)
+}
+
+/// Start listening for datagrams on a bound socket.
+fn listen(socket: net::UdpSocket) -> io::Result<UdpListener> {
+    Ok(UdpListener {
+        io: PollEvented2::new(mio::net::UdpSocket::from_socket(socket)?),
+        buf: vec![0; MAX_DATAGRAM_LEN],
+    })
+}
+
+/// Ask the kernel to report the destination address and interface of each
+/// datagram received on a socket bound to the given address.
+fn enable_packet_info(fd: RawFd, addr: &SocketAddr) -> io::Result<()> {
+    match *addr {
+        SocketAddr::V4(..) => set_option(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, 1),
+        SocketAddr::V6(..) => set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1),
+    }
+}
+
+/// Join the IPv6 all-nodes multicast group on every multicast-capable
+/// interface that has an IPv6 address.
+///
+/// If an interface is given, the group is only joined on that interface.
+/// Returns the names of the interfaces the group was joined on.
+pub fn join_all_nodes(listener: &UdpListener, interface: Option<&str>) -> io::Result<Vec<String>> {
+    let interfaces = getifaddrs()
+        .map_err(nix_to_io)?
+        .filter(|ifaddr| {
+            interface
+                .map(|interface| ifaddr.interface_name == interface)
+                .unwrap_or(true)
+        })
+        .filter(|ifaddr| ifaddr.flags.contains(InterfaceFlags::IFF_MULTICAST))
+        .filter(|ifaddr| match ifaddr.address {
+            Some(SockAddr::Inet(address)) => address.to_std().is_ipv6(),
+            _ => false,
+        })
+        .map(|ifaddr| ifaddr.interface_name)
+        .collect::<BTreeSet<_>>();
+
+    for interface in &interfaces {
+        let index = if_nametoindex(interface.as_str()).map_err(nix_to_io)?;
+        listener.io.get_ref().join_multicast_v6(&ALL_NODES, index)?;
+    }
+
+    Ok(interfaces.into_iter().collect())
+}
+
+/// Receive a single datagram with `recvmsg`, along with its packet info.
+fn recv(fd: RawFd, buf: &mut [u8]) -> io::Result<Datagram> {
+    let mut name: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut control = [0u64; CONTROL_LEN];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let n = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let source = socket_addr(&name)?;
+    let mut datagram = Datagram {
+        payload: buf[..n as usize].to_vec(),
+        source,
+        destination: None,
+        interface_index: None,
+    };
+
+    // Walk the control messages by hand, since `libc` does not provide the
+    // `CMSG_*` macros.
+    let control = unsafe {
+        ::std::slice::from_raw_parts(control.as_ptr() as *const u8, msg.msg_controllen as usize)
+    };
+    let header_len = mem::size_of::<libc::cmsghdr>();
+    let mut offset = 0;
+    while offset + header_len <= control.len() {
+        let header: libc::cmsghdr =
+            unsafe { ptr::read_unaligned(control[offset..].as_ptr() as *const libc::cmsghdr) };
+        let len = header.cmsg_len as usize;
+        if len < header_len || offset + len > control.len() {
+            break;
+        }
+
+        let data = &control[offset + cmsg_align(header_len)..offset + len];
+        match (header.cmsg_level, header.cmsg_type) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO)
+                if data.len() >= mem::size_of::<libc::in_pktinfo>() =>
+            {
+                let info: libc::in_pktinfo =
+                    unsafe { ptr::read_unaligned(data.as_ptr() as *const libc::in_pktinfo) };
+                datagram.destination =
+                    Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)).into());
+                datagram.interface_index = Some(info.ipi_ifindex as u32);
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO)
+                if data.len() >= mem::size_of::<libc::in6_pktinfo>() =>
+            {
+                let info: libc::in6_pktinfo =
+                    unsafe { ptr::read_unaligned(data.as_ptr() as *const libc::in6_pktinfo) };
+                // IPv4 packets received by a dual-stack socket have mapped
+                // addresses.
+                datagram.destination = Some(unmap(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+                datagram.interface_index = Some(info.ipi6_ifindex);
+            }
+            _ => {}
+        }
+
+        offset += cmsg_align(len);
+    }
+
+    Ok(datagram)
+}
+
+/// Convert an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) into the IPv4 address
+/// it maps. Other addresses are returned as-is.
+fn unmap(ip: Ipv6Addr) -> IpAddr {
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, high, low] => {
+            Ipv4Addr::from(u32::from(high) << 16 | u32::from(low)).into()
+        }
+        _ => ip.into(),
+    }
+}
+
+/// Round a control message length up to the alignment of a `cmsghdr`.
+fn cmsg_align(len: usize) -> usize {
+    let align = mem::size_of::<usize>();
+    (len + align - 1) & !(align - 1)
+}
+
+/// Convert a socket address returned by the kernel into a `SocketAddr`.
+fn socket_addr(addr: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match libc::c_int::from(addr.ss_family) {
+        libc::AF_INET => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            Ok(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                u16::from_be(addr.sin_port),
+            )
+            .into())
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+
+            // IPv4 packets received by a dual-stack socket have mapped addresses.
+            if let IpAddr::V4(ip) = unmap(ip) {
+                return Ok(SocketAddrV4::new(ip, u16::from_be(addr.sin6_port)).into());
+            }
+
+            Ok(SocketAddrV6::new(
+                ip,
+                u16::from_be(addr.sin6_port),
+                u32::from_be(addr.sin6_flowinfo),
+                addr.sin6_scope_id,
+            )
+            .into())
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected address family {}", family),
+        )),
+    }
+}
+
+/// Bind a socket to a network interface with `SO_BINDTODEVICE`.
+fn bind_to_device(fd: RawFd, interface: &str) -> io::Result<()> {
+    let rv = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+    if rv < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Get an integer socket option.
+fn get_option(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let rv = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            name,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rv < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(value)
+}
+
+/// Set an integer socket option.
+fn set_option(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let rv = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rv < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Convert a `nix` error into an IO error.
+fn nix_to_io(e: nix::Error) -> io::Error {
+    match e {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        // The other errors are for arguments that could not be passed to the
+        // system call.
+        e => io::Error::new(io::ErrorKind::InvalidInput, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{net::TcpListener, os::unix::io::IntoRawFd};
+
+    #[test]
+    fn test_bind_dual_stack() {
+        let ipv4 = bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0), None).unwrap();
+        let port = ipv4.local_addr().unwrap().port();
+
+        // IPv6 sockets must not claim the IPv4 port.
+        let ipv6 = bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port), None);
+        match ipv6 {
+            Ok(ipv6) => assert_eq!(ipv6.local_addr().unwrap().port(), port),
+            Err(ref e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => {}
+            Err(e) => panic!("could not bind IPv6 socket: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_bind_to_device() {
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+
+        match bind(&addr, Some("lo")) {
+            Ok(..) => {}
+            // Binding to a device requires CAP_NET_RAW on older kernels.
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            Err(e) => panic!("could not bind to lo: {}", e),
+        }
+
+        assert_matches!(
+            bind(&addr, Some("does-not-exist")).map(drop),
+            Err(ref e) if e.raw_os_error() == Some(libc::ENODEV)
+        );
+    }
+
+    #[test]
+    fn test_packet_info() {
+        let listener = bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0), None).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let sender = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"hello", ("127.0.0.1", port)).unwrap();
+
+        let datagram = listener
+            .wait()
+            .next()
+            .expect("Stream ended?")
+            .expect("Could not receive datagram");
+        assert_eq!(
+            datagram,
+            Datagram {
+                payload: b"hello".to_vec(),
+                source: sender.local_addr().unwrap(),
+                destination: Some(Ipv4Addr::LOCALHOST.into()),
+                interface_index: Some(if_nametoindex("lo").unwrap()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unmap() {
+        assert_eq!(
+            unmap("::ffff:192.168.1.2".parse().unwrap()),
+            "192.168.1.2".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            unmap("::192.168.1.2".parse().unwrap()),
+            "::192.168.1.2".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            unmap("ff02::1".parse().unwrap()),
+            "ff02::1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_from_raw_fd() {
+        // A dual-stack socket, like the ones systemd binds by default.
+        let socket = match net::UdpSocket::bind("[::]:0") {
+            Ok(socket) => socket,
+            Err(ref e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => return,
+            Err(e) => panic!("could not bind IPv6 socket: {}", e),
+        };
+        let port = socket.local_addr().unwrap().port();
+        let listener = from_raw_fd(socket.into_raw_fd()).unwrap();
+
+        let sender = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"hello", ("127.0.0.1", port)).unwrap();
+
+        let datagram = listener
+            .wait()
+            .next()
+            .expect("Stream ended?")
+            .expect("Could not receive datagram");
+        assert_eq!(datagram.source, sender.local_addr().unwrap());
+        assert_eq!(datagram.destination, Some(Ipv4Addr::LOCALHOST.into()));
+
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert_matches!(
+            from_raw_fd(tcp.into_raw_fd()).map(drop),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidInput
+        );
+    }
+}